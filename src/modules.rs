@@ -1,2 +1,9 @@
 pub mod app;
+pub mod cache;
+pub mod chromeos;
+pub mod firmware;
+pub mod library;
+pub mod machine;
+pub mod network;
 pub mod qemu;
+pub mod varstore;
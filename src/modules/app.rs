@@ -1,8 +1,15 @@
 use crate::utils::error::Error;
 use crate::utils::shell::{Args, SubCommands, is_available};
 
+pub mod cache;
+pub mod config;
+pub mod image;
+pub mod import_build;
+pub mod info;
 pub mod list;
 pub mod new;
+pub mod nvram;
+pub mod port_forward;
 pub mod recover;
 pub mod remove;
 pub mod run;
@@ -36,6 +43,30 @@ impl App {
             SubCommands::Recover(recover_command) => {
                 recover_command.exec()?;
             }
+            SubCommands::Reinstall(recover_command) => {
+                recover_command.reinstall()?;
+            }
+            SubCommands::Nvram(nvram_command) => {
+                nvram_command.exec()?;
+            }
+            SubCommands::Image(image_command) => {
+                image_command.exec()?;
+            }
+            SubCommands::Cache(cache_command) => {
+                cache_command.exec()?;
+            }
+            SubCommands::ImportBuild(import_build_command) => {
+                import_build_command.exec()?;
+            }
+            SubCommands::PortForward(port_forward_command) => {
+                port_forward_command.exec()?;
+            }
+            SubCommands::Info(info_command) => {
+                info_command.exec()?;
+            }
+            SubCommands::Config(config_command) => {
+                config_command.exec()?;
+            }
         }
         Ok(())
     }
@@ -0,0 +1,43 @@
+use crate::modules::cache;
+use crate::utils::error::Error;
+use clap::{Args, Subcommand};
+
+#[derive(Args)]
+pub struct CacheCommand {
+    #[clap(subcommand)]
+    action: CacheAction,
+}
+
+#[derive(Subcommand)]
+enum CacheAction {
+    /// List images extracted from .zip/.gz/.xz archives
+    List,
+    /// Delete all extracted images
+    Clean,
+}
+
+impl CacheCommand {
+    pub fn exec(&self) -> Result<(), Error> {
+        match self.action {
+            CacheAction::List => {
+                let entries = cache::entries()?;
+                if entries.is_empty() {
+                    println!("The cache is empty.");
+                    return Ok(());
+                }
+                for entry in &entries {
+                    println!("{} ({} MiB)", entry.path.display(), entry.size >> 20);
+                }
+                println!(
+                    "Total: {} MiB",
+                    entries.iter().map(|entry| entry.size).sum::<u64>() >> 20
+                );
+            }
+            CacheAction::Clean => {
+                let freed = cache::clean()?;
+                println!("Removed cached images ({} MiB freed).", freed >> 20);
+            }
+        }
+        Ok(())
+    }
+}
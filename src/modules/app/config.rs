@@ -0,0 +1,65 @@
+use crate::modules::machine::{self, MachineConfig};
+use crate::utils::error::Error;
+use clap::{Args, Subcommand};
+
+#[derive(Args)]
+pub struct ConfigCommand {
+    #[clap(subcommand)]
+    action: ConfigAction,
+}
+
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Show the VM's settings
+    Show { name: String },
+    /// Change a setting, e.g. `config set my-vm memory 8G`
+    Set {
+        name: String,
+        key: String,
+        value: String,
+    },
+    /// Remove a setting, e.g. `config unset my-vm recovery-path`
+    Unset { name: String, key: String },
+}
+
+impl ConfigCommand {
+    pub fn exec(&self) -> Result<(), Error> {
+        match &self.action {
+            ConfigAction::Show { name } => {
+                let config = MachineConfig::load(name)?;
+                println!("Settings of '{}' ({}):", name, config.path().display());
+                for (key, value) in config.entries() {
+                    println!("  {}={}", key, value);
+                }
+                println!("Settable keys:");
+                for setting in machine::SETTINGS {
+                    println!(
+                        "  {:<14} {}",
+                        setting.key.to_lowercase().replace('_', "-"),
+                        setting.description
+                    );
+                }
+            }
+            ConfigAction::Set { name, key, value } => {
+                let mut config = MachineConfig::load(name)?;
+                let setting = machine::setting(key)?;
+                setting.validate(value)?;
+                config.set(setting.key, value);
+                config.save()?;
+                println!("Set {}={} for '{}'", setting.key, value, name);
+            }
+            ConfigAction::Unset { name, key } => {
+                let mut config = MachineConfig::load(name)?;
+                let setting = machine::setting(key)?;
+                if config.get(setting.key).is_none() {
+                    println!("{} is not set for '{}'", setting.key, name);
+                    return Ok(());
+                }
+                config.remove(setting.key);
+                config.save()?;
+                println!("Removed {} from '{}'", setting.key, name);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,192 @@
+use crate::modules::cache;
+use crate::modules::chromeos::{self, ImageInfo};
+use crate::modules::library;
+use crate::modules::machine::MachineConfig;
+use crate::utils::error::Error;
+use crate::utils::time::file_timestamp;
+use clap::{Args, Subcommand};
+use std::path::Path;
+use std::time::SystemTime;
+
+#[derive(Args)]
+pub struct ImageCommand {
+    #[clap(subcommand)]
+    action: ImageAction,
+}
+
+#[derive(Subcommand)]
+enum ImageAction {
+    /// Check a recovery image and show its board, channel and version
+    Inspect { file: String },
+    /// Import a recovery image (.bin, .zip, .gz or .xz) into the library
+    Add {
+        file: String,
+        /// Library id (defaults to <board>-<version>)
+        #[clap(long)]
+        id: Option<String>,
+    },
+    /// List images in the library
+    List,
+    /// Delete an image from the library
+    Remove { image: String },
+    /// Check library images against their recorded SHA-256
+    Verify { image: Option<String> },
+}
+
+impl ImageCommand {
+    pub fn exec(&self) -> Result<(), Error> {
+        match &self.action {
+            ImageAction::Inspect { file } => {
+                let info = chromeos::inspect_image(Path::new(file))?;
+                print_info(file, &info);
+                for partition in &info.gpt.partitions {
+                    println!(
+                        "    {:>2} {:<12} {:>12} bytes",
+                        partition.number,
+                        partition.name,
+                        partition.size()
+                    );
+                }
+                Ok(())
+            }
+            ImageAction::Add { file, id } => {
+                let source = cache::uncompressed(file)?;
+                let info = chromeos::inspect_image(Path::new(&source))?;
+                print_info(&source, &info);
+                let id = id.clone().unwrap_or_else(|| {
+                    format!("{}-{}", info.release.short_board(), info.release.version)
+                });
+                println!("Importing as '{}'...", id);
+                let added = file_timestamp(SystemTime::now());
+                let image = library::add(Path::new(&source), file, &info, &id, added)?;
+                println!("Added '{}' (sha256 {})", image.id, image.sha256);
+                Ok(())
+            }
+            ImageAction::List => {
+                let images = library::images()?;
+                if images.is_empty() {
+                    println!("The image library is empty. Add images with `images add <file>`.");
+                    return Ok(());
+                }
+                for image in images {
+                    println!("- {}", image.id);
+                    println!("  Board: {}", image.board);
+                    println!("  Version: {} ({})", image.version, image.channel);
+                    println!("  Size: {} MiB", image.size >> 20);
+                    println!("  SHA-256: {}", image.sha256);
+                }
+                Ok(())
+            }
+            ImageAction::Remove { image } => {
+                let image = library::find(image)?;
+                library::remove(&image)?;
+                println!("Removed '{}' from the library.", image.id);
+                Ok(())
+            }
+            ImageAction::Verify { image } => {
+                let images = match image {
+                    Some(reference) => vec![library::find(reference)?],
+                    None => library::images()?,
+                };
+                let mut failed = Vec::new();
+                for image in images {
+                    println!("Verifying '{}'...", image.id);
+                    match library::verify(&image) {
+                        Ok(true) => println!("  OK"),
+                        Ok(false) => {
+                            println!("  Checksum mismatch");
+                            failed.push(image.id);
+                        }
+                        Err(err) => {
+                            println!("  {}", err.message());
+                            failed.push(image.id);
+                        }
+                    }
+                }
+                if !failed.is_empty() {
+                    return Err(Error::Io(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("Verification failed for {}", failed.join(", ")),
+                    )));
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Resolves the install media given as `--iso <path>` or `--image <id>` to a
+/// file QEMU can boot.
+pub fn install_media(iso: Option<&str>, image: Option<&str>) -> Result<String, Error> {
+    match (iso, image) {
+        (_, Some(reference)) => {
+            let image = library::find(reference)?;
+            println!("Using library image '{}'", image.id);
+            Ok(image.path()?.to_string_lossy().into_owned())
+        }
+        (Some(iso), None) => cache::uncompressed(iso),
+        (None, None) => Err(Error::Io(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Specify the install media with --iso or --image",
+        ))),
+    }
+}
+
+pub fn print_info(file: &str, info: &ImageInfo) {
+    let release = &info.release;
+    println!("Image: {}", file);
+    if !release.name.is_empty() {
+        println!("  Name: {}", release.name);
+    }
+    println!("  Board: {}", release.board);
+    println!("  Channel: {}", release.short_channel());
+    println!("  Version: {}", release.version);
+    if !release.milestone.is_empty() {
+        println!("  Milestone: {}", release.milestone);
+    }
+    println!("  Size: {} bytes", info.size);
+}
+
+/// Inspects an install/recovery image before handing it to QEMU, so a wrong or
+/// truncated download is caught before booting it.
+pub fn preflight(file: &str) -> Result<ImageInfo, Error> {
+    let info = chromeos::inspect_image(Path::new(file)).map_err(|err| {
+        let Error::Io(io) = err else {
+            return err;
+        };
+        Error::Io(std::io::Error::new(
+            io.kind(),
+            format!("{} (use --skip-check to boot it anyway)", io),
+        ))
+    })?;
+    print_info(file, &info);
+    Ok(info)
+}
+
+/// Resolves `--install <image>`, which may be a file or a library reference.
+pub fn media_reference(reference: &str) -> Result<String, Error> {
+    if Path::new(reference).exists() {
+        install_media(Some(reference), None)
+    } else {
+        install_media(None, Some(reference))
+    }
+}
+
+/// Runs the preflight on install media for an existing machine and refuses
+/// images built for another board than the one it was installed from.
+pub fn check_for_machine(config: &MachineConfig, media: &str, path: &str) -> Result<(), Error> {
+    let info = preflight(path)?;
+    let board = info.release.short_board();
+    if let Some(expected) = config.get("BOARD")
+        && expected != board
+    {
+        return Err(Error::Io(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "'{}' is a {} image but VM '{}' runs {}. Use --skip-check to install it anyway.",
+                media, board, config.name, expected
+            ),
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,272 @@
+use crate::modules::app::image;
+use crate::modules::app::new::copy_image;
+use crate::modules::app::recover::ensure_disk_not_in_use;
+use crate::modules::app::run::{LaunchOptions, prepare_qemu};
+use crate::modules::machine::{self, MachineConfig};
+use crate::modules::network::{self, PortForward};
+use crate::modules::qemu;
+use crate::utils::error::Error;
+use clap::{Args, ValueEnum};
+use std::fs;
+use std::net::TcpListener;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+/// The kinds of image `cros build-image` produces.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum BuildImage {
+    Test,
+    Dev,
+    Base,
+}
+
+impl BuildImage {
+    fn file_name(self) -> &'static str {
+        match self {
+            BuildImage::Test => "chromiumos_test_image.bin",
+            BuildImage::Dev => "chromiumos_image.bin",
+            BuildImage::Base => "chromiumos_base_image.bin",
+        }
+    }
+
+    /// The `cros build-image` target producing this image.
+    fn build_target(self) -> &'static str {
+        match self {
+            BuildImage::Test => "test",
+            BuildImage::Dev => "dev",
+            BuildImage::Base => "base",
+        }
+    }
+
+    /// Base images have no SSH server to log in to.
+    fn has_ssh(self) -> bool {
+        self != BuildImage::Base
+    }
+}
+
+#[derive(Args)]
+pub struct ImportBuildCommand {
+    board: String,
+    /// ChromiumOS checkout the build was made in (default: ~/chromiumos)
+    #[clap(long)]
+    path: Option<String>,
+    #[clap(long, value_enum, default_value = "test")]
+    image: BuildImage,
+    /// Machine to create or update (default: the board name)
+    #[clap(long)]
+    name: Option<String>,
+    #[clap(long, default_value = "64G")]
+    disk_size: String,
+    /// Host port forwarded to the guest's SSH server
+    #[clap(long, default_value_t = 9222)]
+    ssh_port: u16,
+    /// Only import the build; do not start the machine
+    #[clap(long)]
+    no_start: bool,
+}
+
+impl ImportBuildCommand {
+    pub fn exec(&self) -> Result<(), Error> {
+        let checkout = self.checkout()?;
+        let build = latest_build(&checkout, &self.board, self.image)?;
+        println!("Importing {}", build.display());
+        let info = image::preflight(&build.to_string_lossy())?;
+        let board = info.release.short_board().to_string();
+
+        let name = self.name.as_deref().unwrap_or(&self.board);
+        let mut config = if machine::machine_names()?.iter().any(|n| n == name) {
+            let config = MachineConfig::load(name)?;
+            self.check_update(&config, &board)?;
+            config
+        } else {
+            let mut config = MachineConfig::create(name)?;
+            let firmware = qemu::detect_arch()?.find_firmware(false)?;
+            println!(
+                "Using firmware {} with vars template {}",
+                firmware.code.display(),
+                firmware.vars_template.display()
+            );
+            config.set("VM_NAME", name);
+            config.set("FIRMWARE", "uefi");
+            config.set_firmware(&firmware);
+            config
+        };
+        if !self.no_start {
+            ensure_port_free(self.ssh_port)?;
+        }
+
+        let disk_path = config.disk_path()?;
+        ensure_disk_not_in_use(&config, &disk_path)?;
+        if disk_path.exists() && !disk_path.is_file() {
+            return Err(Error::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "The disk of '{}' ({}) is not a regular file; choose another --name.",
+                    name,
+                    disk_path.display()
+                ),
+            )));
+        }
+        // The old disk stays usable until the new one is complete.
+        let staging = disk_path.with_extension("import.img");
+        let _ = fs::remove_file(&staging);
+        if let Err(err) = copy_image(&build, &staging, &self.disk_size) {
+            let _ = fs::remove_file(&staging);
+            return Err(err);
+        }
+        if disk_path.exists() {
+            println!("Replacing the disk of '{}'", name);
+        }
+        fs::rename(&staging, &disk_path)?;
+
+        config.set("SOURCE_IMAGE", build.display());
+        config.set("BOARD", &board);
+        config.set("HDD_SIZE", &self.disk_size);
+        let mut forwards = config.port_forwards()?;
+        forwards.retain(|forward| forward.name != "ssh");
+        forwards.push(PortForward {
+            name: "ssh".to_string(),
+            protocol: "tcp".to_string(),
+            host_addr: network::DEFAULT_HOST_ADDR,
+            host_port: self.ssh_port,
+            guest_port: 22,
+        });
+        config.set_port_forwards(&forwards);
+        config.remove("SSH_KEY");
+        if self.image.has_ssh() {
+            match install_testing_key(&checkout, &config)? {
+                Some(key) => config.set("SSH_KEY", key.display()),
+                None => println!(
+                    "Warning: no testing SSH key found in {}; log in with your own key.",
+                    checkout.display()
+                ),
+            }
+        }
+        config.save()?;
+
+        println!("Imported {} into '{}'.", build.display(), name);
+        if self.image.has_ssh() {
+            println!(
+                "Deploy to it with `cros deploy localhost:{} <package>` once it has booted.",
+                self.ssh_port
+            );
+        }
+        if self.no_start {
+            println!("Start it with `run {}`.", name);
+            return Ok(());
+        }
+        prepare_qemu(&mut config, "run", None, None, &LaunchOptions::default())?
+            .run()
+            .map_err(|failure| failure.error)
+    }
+
+    fn checkout(&self) -> Result<PathBuf, Error> {
+        let checkout = match &self.path {
+            Some(path) => PathBuf::from(path),
+            None => dirs::home_dir()
+                .ok_or_else(|| {
+                    Error::Io(std::io::Error::new(
+                        std::io::ErrorKind::NotFound,
+                        "Home directory not found",
+                    ))
+                })?
+                .join("chromiumos"),
+        };
+        if !checkout.join("src").is_dir() {
+            return Err(Error::Io(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!(
+                    "'{}' is not a ChromiumOS checkout; pass its location with --path.",
+                    checkout.display()
+                ),
+            )));
+        }
+        Ok(checkout)
+    }
+
+    /// Only machines copied from an image (by `import-build` or
+    /// `new --from-image`) for the same board are overwritten, so a mistyped
+    /// name cannot wipe an installed machine.
+    fn check_update(&self, config: &MachineConfig, board: &str) -> Result<(), Error> {
+        if config.get("SOURCE_IMAGE").is_none() {
+            return Err(Error::Io(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!(
+                    "VM '{}' was not created from an image; choose another --name.",
+                    config.name
+                ),
+            )));
+        }
+        if let Some(recorded) = config.get("BOARD")
+            && recorded != board
+        {
+            return Err(Error::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "VM '{}' runs {} builds, not {}; choose another --name.",
+                    config.name, recorded, board
+                ),
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Finds the image of the newest build for `board`: the one `latest` points
+/// to, or else the most recently modified build directory that has it.
+fn latest_build(checkout: &Path, board: &str, image: BuildImage) -> Result<PathBuf, Error> {
+    let images_dir = checkout.join("src/build/images").join(board);
+    let latest = images_dir.join("latest").join(image.file_name());
+    if latest.is_file() {
+        return Ok(fs::canonicalize(latest)?);
+    }
+    let newest = fs::read_dir(&images_dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path().join(image.file_name()))
+        .filter_map(|path| Some((fs::metadata(&path).ok()?.modified().ok()?, path)))
+        .max();
+    match newest {
+        Some((_, path)) => Ok(path),
+        None => Err(Error::Io(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!(
+                "No {} found under {}. Build one with `cros build-image --board={} {}`.",
+                image.file_name(),
+                images_dir.display(),
+                board,
+                image.build_target()
+            ),
+        ))),
+    }
+}
+
+/// Copies the checkout's testing key next to the machine, readable only by
+/// the owner as ssh requires.
+fn install_testing_key(checkout: &Path, config: &MachineConfig) -> Result<Option<PathBuf>, Error> {
+    let source = checkout.join("chromite/ssh_keys/testing_rsa");
+    if !source.is_file() {
+        return Ok(None);
+    }
+    let key = config.ssh_key_path()?;
+    fs::copy(&source, &key)?;
+    fs::set_permissions(&key, fs::Permissions::from_mode(0o600))?;
+    Ok(Some(key))
+}
+
+/// QEMU only reports a taken forwarding port after it has started, so check
+/// it up front.
+fn ensure_port_free(port: u16) -> Result<(), Error> {
+    TcpListener::bind(("127.0.0.1", port))
+        .map(|_| ())
+        .map_err(|err| {
+            Error::Io(std::io::Error::new(
+                err.kind(),
+                format!(
+                    "Port {} is not available ({}); is the machine still running? Pick another one with --ssh-port.",
+                    port, err
+                ),
+            ))
+        })
+}
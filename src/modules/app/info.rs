@@ -0,0 +1,96 @@
+use crate::modules::chromeos::{self, InstalledRelease};
+use crate::modules::machine::MachineConfig;
+use crate::utils::error::Error;
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::time::UNIX_EPOCH;
+
+#[derive(Args)]
+pub struct InfoCommand {
+    name: String,
+}
+
+impl InfoCommand {
+    pub fn exec(&self) -> Result<(), Error> {
+        let config = MachineConfig::load(&self.name)?;
+        let disk_path = config.disk_path()?;
+
+        println!("VM: {}", config.name);
+        println!("  Disk: {}", disk_path.display());
+        if let Ok(metadata) = fs::metadata(&disk_path)
+            && metadata.is_file()
+        {
+            println!("  Disk Size: {} MiB", metadata.len() >> 20);
+        }
+        println!("  Memory: {}", config.get("MEMORY").unwrap_or("N/A"));
+        println!("  CPU Cores: {}", config.get("CPU_CORES").unwrap_or("N/A"));
+        println!("  CPU Model: {}", config.get("CPU_MODEL").unwrap_or("N/A"));
+        println!("  Firmware: {}", config.firmware_type()?);
+        if config.get_bool("SECURE_BOOT") {
+            println!("  Secure Boot: Enabled");
+        }
+
+        match installed_release(&config) {
+            Ok(Some(installed)) => {
+                let release = &installed.release;
+                println!("  ChromeOS:");
+                println!("    Version: {}", release.version);
+                println!("    Channel: {}", release.short_channel());
+                println!("    Board: {}", release.board);
+                if !release.milestone.is_empty() {
+                    println!("    Milestone: {}", release.milestone);
+                }
+                println!("    Active Root: {}", installed.root);
+            }
+            Ok(None) => println!("  ChromeOS: not installed"),
+            Err(err) => println!("  ChromeOS: unknown ({})", err.message()),
+        }
+        Ok(())
+    }
+}
+
+/// `machines/<name>.release`: the last release read from the disk, valid as
+/// long as the disk has not been modified since.
+#[derive(Serialize, Deserialize)]
+struct CachedRelease {
+    disk_size: u64,
+    disk_mtime: u128,
+    installed: Option<InstalledRelease>,
+}
+
+/// Reads the ChromeOS release installed on the machine's disk, reusing the
+/// cached result while the disk's modification time and size are unchanged.
+pub fn installed_release(config: &MachineConfig) -> Result<Option<InstalledRelease>, Error> {
+    let disk_path = config.raw_disk_path()?;
+    let Ok(metadata) = fs::metadata(&disk_path) else {
+        return Ok(None);
+    };
+    let disk_mtime = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    let disk_size = metadata.len();
+
+    let cache_path = config.release_cache_path()?;
+    if let Ok(content) = fs::read(&cache_path)
+        && let Ok(cached) = serde_json::from_slice::<CachedRelease>(&content)
+        && cached.disk_mtime == disk_mtime
+        && cached.disk_size == disk_size
+    {
+        return Ok(cached.installed);
+    }
+
+    let installed = chromeos::installed_release(&disk_path)?;
+    let cached = CachedRelease {
+        disk_size,
+        disk_mtime,
+        installed,
+    };
+    // The cache is only an optimisation; failing to write it is not an error.
+    if let Ok(json) = serde_json::to_vec(&cached) {
+        let _ = fs::write(&cache_path, json);
+    }
+    Ok(cached.installed)
+}
@@ -1,50 +1,40 @@
+use crate::modules::app::info::installed_release;
+use crate::modules::machine::{self, MachineConfig};
 use crate::utils::error::Error;
-use dirs;
-use std::collections::HashMap;
-use std::fs;
 
 pub fn list() -> Result<(), Error> {
-    let machines_dir = dirs::home_dir()
-        .ok_or_else(|| {
-            Error::Io(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                "Home directory not found",
-            ))
-        })?
-        .join(".chromeos-launcher/machines");
-
-    if !machines_dir.exists() {
+    let names = machine::machine_names()?;
+    if names.is_empty() {
         println!("No virtual machines found.");
         return Ok(());
     }
 
-    let entries = fs::read_dir(machines_dir)?;
     println!("Existing VMs:");
-    for entry in entries {
-        let entry = entry?;
-        let path = entry.path();
-        if path.is_file() {
-            let vm_name = path.file_name().unwrap().to_str().unwrap().to_string();
-            let config_content = fs::read_to_string(&path)?;
-            let config: HashMap<_, _> = config_content
-                .lines()
-                .filter_map(|line| {
-                    let mut parts = line.splitn(2, '=');
-                    Some((
-                        parts.next()?.trim(),
-                        parts.next()?.trim().trim_matches('\''),
-                    ))
-                })
-                .collect();
-
-            let memory = config.get("MEMORY").unwrap_or(&"N/A");
-            let cpu_cores = config.get("CPU_CORES").unwrap_or(&"N/A");
-            let ovmf_code = config.get("OVMF_CODE").unwrap_or(&"N/A");
+    for vm_name in names {
+        let config = MachineConfig::load(&vm_name)?;
+        let memory = config.get("MEMORY").unwrap_or("N/A");
+        let cpu_cores = config.get("CPU_CORES").unwrap_or("N/A");
+        let ovmf_code = config.get("OVMF_CODE").unwrap_or("N/A");
+        let ovmf_vars = config.get("OVMF_VARS").unwrap_or("N/A");
 
-            println!("  - Name: {}", vm_name);
-            println!("    Memory: {}", memory);
-            println!("    CPU Cores: {}", cpu_cores);
+        println!("  - Name: {}", vm_name);
+        println!("    Memory: {}", memory);
+        println!("    CPU Cores: {}", cpu_cores);
+        if config.get("FIRMWARE") == Some("bios") {
+            println!("    Firmware: Legacy BIOS");
+        } else {
             println!("    OVMF Code: {}", ovmf_code);
+            println!("    OVMF Vars: {}", ovmf_vars);
+        }
+        match installed_release(&config) {
+            Ok(Some(installed)) => println!(
+                "    ChromeOS: {} ({}, {})",
+                installed.release.version,
+                installed.release.short_channel(),
+                installed.release.short_board()
+            ),
+            Ok(None) => println!("    ChromeOS: not installed"),
+            Err(err) => println!("    ChromeOS: unknown ({})", err.message()),
         }
     }
 
@@ -1,21 +1,33 @@
 use crate::utils::error::Error;
 use crate::utils::shell::is_available;
 use clap::Args;
-use dirs;
 use std::fs;
-use std::path::PathBuf;
-use std::process::Command;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
-use crate::modules::app::run::run_qemu;
-use crate::utils::resource::ResourceValue;
+use crate::modules::app::image;
+use crate::modules::app::run::{LaunchOptions, prepare_qemu};
+use crate::modules::cache;
+use crate::modules::chromeos::{self, gpt};
+use crate::modules::firmware::{self, Firmware, FirmwareType};
+use crate::modules::machine::{self, MachineConfig};
+use crate::modules::qemu::{self, Gpu, MediaAttach};
+use crate::utils::progress::ProgressReader;
+use crate::utils::resource::{ResourceValue, parse_disk_size};
+use crate::utils::sparse::sparse_copy;
 
 #[derive(Args)]
 pub struct NewCommand {
     #[clap(long)]
     name: String,
+    #[clap(
+        long,
+        required_unless_present_any = ["image", "existing_disk", "from_image"],
+        conflicts_with = "image"
+    )]
+    iso: Option<String>,
     #[clap(long)]
-    iso: String,
+    image: Option<String>,
     #[clap(long)]
     disk: String,
     #[clap(long, default_value = "64G")]
@@ -28,33 +40,101 @@ pub struct NewCommand {
     cpu_model: String,
     #[clap(long)]
     ovmf_code: Option<String>,
+    #[clap(long, requires = "ovmf_code")]
+    ovmf_vars: Option<String>,
+    #[clap(long)]
+    secure_boot: bool,
+    #[clap(long, value_enum, default_value_t = FirmwareType::Uefi)]
+    firmware: FirmwareType,
+    #[clap(long)]
+    skip_check: bool,
+    #[clap(long, value_enum)]
+    media_attach: Option<MediaAttach>,
+    /// Graphics card (default: virtio-gl when the display supports OpenGL)
+    #[clap(long, value_enum)]
+    gpu: Option<Gpu>,
+    /// Only create the machine; do not start the installer
+    #[clap(long)]
+    no_start: bool,
+    /// Adopt --disk, which already has ChromeOS installed, instead of installing
+    #[clap(long, conflicts_with_all = ["iso", "image"])]
+    existing_disk: bool,
+    /// Copy a bootable ChromiumOS image (e.g. chromiumos_test_image.bin) into
+    /// --disk instead of installing
+    #[clap(long, conflicts_with_all = ["iso", "image", "existing_disk"])]
+    from_image: Option<String>,
 }
 
 impl NewCommand {
     pub fn exec(&self) -> Result<(), Error> {
         println!("Creating new VM...");
 
-        let config_dir = dirs::home_dir()
-            .ok_or_else(|| {
-                Error::Io(std::io::Error::new(
-                    std::io::ErrorKind::NotFound,
-                    "Home directory not found",
-                ))
-            })?
-            .join(".chromeos-launcher");
-        let machines_dir = config_dir.join("machines");
-        fs::create_dir_all(&machines_dir)?;
-
-        let config_file = machines_dir.join(&self.name);
-        if config_file.exists() {
-            return Err(Error::Io(std::io::Error::new(
-                std::io::ErrorKind::AlreadyExists,
-                format!("VM '{}' already exists", self.name),
-            )));
-        }
-
+        let mut config = MachineConfig::create(&self.name)?;
         let disk_path = PathBuf::from(&self.disk);
-        if !disk_path.exists() {
+        let source = match &self.from_image {
+            Some(path) => Some(cache::uncompressed(path)?),
+            None => None,
+        };
+        let iso = if self.existing_disk || source.is_some() {
+            None
+        } else {
+            Some(image::install_media(
+                self.iso.as_deref(),
+                self.image.as_deref(),
+            )?)
+        };
+        let board = match iso.as_ref().or(source.as_ref()) {
+            _ if self.skip_check => None,
+            Some(media) => {
+                let info = image::preflight(media)?;
+                Some(info.release.short_board().to_string())
+            }
+            None => Some(self.installed_board(&disk_path)?),
+        };
+        let firmware = match self.firmware {
+            FirmwareType::Uefi => {
+                let firmware = self.select_firmware()?;
+                println!(
+                    "Using firmware {} with vars template {}",
+                    firmware.code.display(),
+                    firmware.vars_template.display()
+                );
+                Some(firmware)
+            }
+            FirmwareType::Bios => {
+                if self.secure_boot || self.ovmf_code.is_some() {
+                    return Err(Error::Io(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        "--secure-boot and --ovmf-code require --firmware uefi",
+                    )));
+                }
+                println!("Using legacy BIOS (SeaBIOS)");
+                None
+            }
+        };
+
+        let disk_created = !disk_path.exists();
+        if let Some(source) = &source {
+            if !disk_created {
+                return Err(Error::Io(std::io::Error::new(
+                    std::io::ErrorKind::AlreadyExists,
+                    format!(
+                        "Disk '{}' already exists; --from-image needs a new disk path",
+                        self.disk
+                    ),
+                )));
+            }
+            if let Err(err) = copy_image(Path::new(source), &disk_path, &self.disk_size) {
+                let _ = fs::remove_file(&disk_path);
+                return Err(err);
+            }
+        } else if disk_created {
+            if self.existing_disk {
+                return Err(Error::Io(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("Disk '{}' does not exist", self.disk),
+                )));
+            }
             if !is_available("qemu-img") {
                 println!("qemu-img is not installed. Please install it to create a disk image.");
                 return Ok(());
@@ -63,43 +143,198 @@ impl NewCommand {
                 "Creating new disk image at '{}' with size {}",
                 self.disk, self.disk_size
             );
-            Command::new("qemu-img")
-                .arg("create")
-                .arg("-f")
-                .arg("raw")
-                .arg(&self.disk)
-                .arg(&self.disk_size)
-                .status()?;
+            qemu::create_disk(&disk_path, "raw", parse_disk_size(&self.disk_size)?)?;
         }
 
-        let config_content = format!(
-            "VM_NAME={}\nISO_PATH={}\nDISK_PATH={}\nCPU_CORES={}\nMEMORY={}\nCPU_MODEL={}\nOVMF_CODE={}\n",
-            self.name,
-            self.iso,
-            self.disk,
-            self.cpu_cores.to_string(),
-            self.memory.to_string(),
-            self.cpu_model,
-            self.ovmf_code.as_deref().unwrap_or("")
-        );
-        fs::write(config_file, config_content)?;
+        config.set("VM_NAME", &self.name);
+        if let Some(iso) = &iso {
+            config.set("ISO_PATH", self.iso.as_deref().unwrap_or(iso));
+        }
+        if let Some(image) = &self.image {
+            config.set("IMAGE", image);
+        }
+        if let Some(from_image) = &self.from_image {
+            config.set("SOURCE_IMAGE", from_image);
+        }
+        config.set("DISK_PATH", &self.disk);
+        if !self.existing_disk {
+            config.set("HDD_SIZE", &self.disk_size);
+        }
+        config.set("CPU_CORES", &self.cpu_cores);
+        config.set("MEMORY", &self.memory);
+        config.set("CPU_MODEL", &self.cpu_model);
+        config.set("FIRMWARE", self.firmware);
+        config.set("SECURE_BOOT", self.secure_boot);
+        if let Some(board) = &board {
+            config.set("BOARD", board);
+        }
+        if let Some(media_attach) = self.media_attach {
+            config.set("MEDIA_ATTACH", media_attach);
+        }
+        if let Some(gpu) = self.gpu {
+            config.set("GPU", gpu);
+        }
+        if let Some(firmware) = &firmware {
+            config.set_firmware(firmware);
+        }
+        config.save()?;
 
         println!("Configuration for '{}' created successfully.", self.name);
-        println!("Starting installation...");
+        if self.no_start {
+            match &iso {
+                Some(_) => println!(
+                    "Start the installer with `run {} --install {}`.",
+                    self.name,
+                    self.iso
+                        .as_deref()
+                        .or(self.image.as_deref())
+                        .unwrap_or_default()
+                ),
+                None => println!("Start it with `run {}`.", self.name),
+            }
+            return Ok(());
+        }
+
+        let mode = if iso.is_some() {
+            println!("Starting installation...");
+            "install"
+        } else {
+            "run"
+        };
+        let launch = match prepare_qemu(&mut config, mode, iso, None, &LaunchOptions::default()) {
+            Ok(launch) => launch,
+            Err(err) => return Err(self.roll_back(&config, disk_created, err)),
+        };
+        launch.run().map_err(|failure| {
+            if failure.before_boot {
+                self.roll_back(&config, disk_created, failure.error)
+            } else {
+                println!(
+                    "The VM '{}' was kept. Start it again with `run {}`, or delete it with `remove {}`.",
+                    self.name, self.name, self.name
+                );
+                failure.error
+            }
+        })
+    }
+
+    /// Reads the board of the ChromeOS already installed on an adopted disk.
+    fn installed_board(&self, disk_path: &Path) -> Result<String, Error> {
+        match chromeos::installed_release(disk_path)? {
+            Some(installed) => {
+                let release = &installed.release;
+                println!(
+                    "Found ChromeOS {} ({}, {}) on {}",
+                    release.version,
+                    release.short_channel(),
+                    release.board,
+                    installed.root
+                );
+                Ok(release.short_board().to_string())
+            }
+            None => Err(Error::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "'{}' has no ChromeOS installed. Install it with --iso or --image, or use --skip-check to adopt it anyway.",
+                    disk_path.display()
+                ),
+            ))),
+        }
+    }
+
+    /// Removes what this command created when the installer could not be
+    /// started, so the name can simply be used again.
+    fn roll_back(&self, config: &MachineConfig, disk_created: bool, err: Error) -> Error {
+        let mut files = vec![config.path().to_path_buf()];
+        files.extend(config.vars_path());
+        if disk_created {
+            files.push(PathBuf::from(&self.disk));
+        }
+        for file in files {
+            let _ = fs::remove_file(file);
+        }
+        if let Ok(last_run_file) = machine::config_dir().map(|dir| dir.join("last_run"))
+            && fs::read_to_string(&last_run_file).is_ok_and(|name| name.trim() == self.name)
+        {
+            let _ = fs::remove_file(last_run_file);
+        }
+        println!("Removed the partially created VM '{}'.", self.name);
+        err
+    }
+
+    /// Picks the firmware pair recorded for the new machine: the pair given on
+    /// the command line, the vars template matching `--ovmf-code`, or the best
+    /// match installed on the host.
+    fn select_firmware(&self) -> Result<Firmware, Error> {
+        let firmware = match (&self.ovmf_code, &self.ovmf_vars) {
+            (Some(code), Some(vars)) => {
+                let firmware =
+                    Firmware::from_paths(PathBuf::from(code), PathBuf::from(vars), "raw");
+                for path in [&firmware.code, &firmware.vars_template] {
+                    if !path.is_file() {
+                        return Err(Error::Io(std::io::Error::new(
+                            std::io::ErrorKind::NotFound,
+                            format!("Firmware file not found: {}", path.display()),
+                        )));
+                    }
+                }
+                Ok(firmware)
+            }
+            (Some(code), None) => firmware::pair_for_code(Path::new(code)),
+            _ => qemu::detect_arch()?.find_firmware(self.secure_boot),
+        }?;
+        if self.secure_boot && !firmware.has_feature("secure-boot") {
+            return Err(Error::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "{} is not a Secure Boot firmware build (expected a *.secboot.fd image).",
+                    firmware.code.display()
+                ),
+            )));
+        }
+        Ok(firmware)
+    }
+}
 
-        run_qemu(
-            &self.name,
-            "install",
-            Some(self.iso.clone()),
-            None, // No recovery path in new command
-            &self.disk,
-            &self.cpu_cores,
-            &self.memory,
-            &self.cpu_model,
-            self.ovmf_code.clone(),
-            false, // No 3D accel in new command
-        )?;
+/// Writes a bootable image into a new raw disk, skipping zero blocks, and
+/// grows it to `disk_size` with the backup GPT moved to the new end.
+pub fn copy_image(source: &Path, disk_path: &Path, disk_size: &str) -> Result<(), Error> {
+    // qemu-img rounds up to whole sectors as well.
+    let requested = parse_disk_size(disk_size)?.next_multiple_of(gpt::SECTOR_SIZE);
+    let mut input = fs::File::open(source)?;
+    let image_size = input.metadata()?.len();
+    let disk_size = if requested < image_size {
+        println!(
+            "Warning: {} is smaller than the image; keeping the image size of {} MiB",
+            disk_size,
+            image_size >> 20
+        );
+        image_size
+    } else {
+        requested
+    };
 
-        Ok(())
+    println!(
+        "Copying {} into '{}'",
+        source.display(),
+        disk_path.display()
+    );
+    let mut disk = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(disk_path)?;
+    sparse_copy(&mut ProgressReader::new(&mut input, image_size), &mut disk)?;
+    eprintln!();
+    disk.set_len(disk_size)?;
+    if disk_size > image_size {
+        gpt::relocate_backup(&mut disk, disk_size).map_err(|err| {
+            Error::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Could not grow the partition table: {}", err),
+            ))
+        })?;
     }
+    disk.sync_all()?;
+    Ok(())
 }
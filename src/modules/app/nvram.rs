@@ -0,0 +1,352 @@
+use crate::modules::machine::MachineConfig;
+use crate::modules::qemu;
+use crate::modules::varstore::{EFI_GLOBAL_VARIABLE, VarStore, boot_variable_name, parse_boot_id};
+use crate::utils::error::Error;
+use crate::utils::process::processes_using;
+use crate::utils::time::file_timestamp;
+use clap::{Args, Subcommand};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+#[derive(Args)]
+pub struct NvramCommand {
+    #[clap(subcommand)]
+    action: NvramAction,
+}
+
+#[derive(Subcommand)]
+enum NvramAction {
+    /// Re-seed the VM's UEFI variables from the firmware template
+    Reset { name: String },
+    /// Save a timestamped copy of the VM's UEFI variables
+    Backup { name: String },
+    /// Restore the VM's UEFI variables from a backup (the newest by default)
+    Restore {
+        name: String,
+        backup: Option<String>,
+    },
+    /// Show the VM's UEFI variable store and its backups
+    Show { name: String },
+    /// Inspect or edit the VM's UEFI boot entries
+    Boot {
+        #[clap(subcommand)]
+        action: BootAction,
+    },
+}
+
+#[derive(Subcommand)]
+enum BootAction {
+    /// List boot entries in boot order
+    List { name: String },
+    /// Move the given entries (e.g. 0003 0001) to the front of the boot order
+    Order {
+        name: String,
+        #[clap(required = true)]
+        entries: Vec<String>,
+    },
+    /// Delete a boot entry and drop it from the boot order
+    Delete { name: String, entry: String },
+}
+
+impl NvramCommand {
+    pub fn exec(&self) -> Result<(), Error> {
+        match &self.action {
+            NvramAction::Reset { name } => reset(name),
+            NvramAction::Backup { name } => {
+                let config = MachineConfig::load(name)?;
+                let vars = existing_vars(&config)?;
+                let backup = backup(&config, &vars)?;
+                println!("Saved NVRAM of '{}' to {}", name, backup.display());
+                Ok(())
+            }
+            NvramAction::Restore { name, backup } => restore(name, backup.as_deref()),
+            NvramAction::Show { name } => show(name),
+            NvramAction::Boot { action } => match action {
+                BootAction::List { name } => boot_list(name),
+                BootAction::Order { name, entries } => boot_order(name, entries),
+                BootAction::Delete { name, entry } => boot_delete(name, entry),
+            },
+        }
+    }
+}
+
+/// Re-seeds the machine's variable store from its firmware template, keeping a
+/// backup of the current one.
+pub fn reset(name: &str) -> Result<(), Error> {
+    let mut config = MachineConfig::load(name)?;
+    config.ensure_uefi()?;
+    let firmware = config.firmware_for_new_vars(&qemu::detect_arch()?)?;
+    let vars = config.vars_path()?;
+    ensure_not_in_use(name, &vars)?;
+
+    if vars.exists() {
+        let saved = backup(&config, &vars)?;
+        println!("Saved current NVRAM to {}", saved.display());
+    }
+    fs::copy(&firmware.vars_template, &vars)?;
+    println!(
+        "Reset NVRAM of '{}' from {}",
+        name,
+        firmware.vars_template.display()
+    );
+    Ok(())
+}
+
+fn restore(name: &str, backup_name: Option<&str>) -> Result<(), Error> {
+    let config = MachineConfig::load(name)?;
+    config.ensure_uefi()?;
+    let vars = config.vars_path()?;
+    ensure_not_in_use(name, &vars)?;
+
+    let backups = list_backups(&config)?;
+    let source = match backup_name {
+        Some(wanted) => backups
+            .iter()
+            .find(|path| {
+                path.file_name().and_then(|n| n.to_str()) == Some(wanted)
+                    || path.file_stem().and_then(|n| n.to_str()) == Some(wanted)
+            })
+            .cloned()
+            .ok_or_else(|| {
+                Error::Io(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("Backup '{}' not found for '{}'.", wanted, name),
+                ))
+            })?,
+        None => backups.last().cloned().ok_or_else(|| {
+            Error::Io(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("No NVRAM backups found for '{}'.", name),
+            ))
+        })?,
+    };
+
+    if vars.exists() {
+        let saved = backup(&config, &vars)?;
+        println!("Saved current NVRAM to {}", saved.display());
+    }
+    fs::copy(&source, &vars)?;
+    println!("Restored NVRAM of '{}' from {}", name, source.display());
+    Ok(())
+}
+
+fn show(name: &str) -> Result<(), Error> {
+    let config = MachineConfig::load(name)?;
+    config.ensure_uefi()?;
+    let vars = config.vars_path()?;
+
+    println!("NVRAM of '{}':", name);
+    println!("  File: {}", vars.display());
+    match fs::metadata(&vars) {
+        Ok(metadata) => println!("  Size: {} bytes", metadata.len()),
+        Err(_) => println!("  Not created yet (seeded on first boot)"),
+    }
+    println!(
+        "  Template: {}",
+        config.get("OVMF_VARS").unwrap_or("(not recorded)")
+    );
+    let pids = processes_using(&vars);
+    if !pids.is_empty() {
+        println!("  In use by PID {}", join_pids(&pids));
+    }
+
+    let backups = list_backups(&config)?;
+    if backups.is_empty() {
+        println!("  Backups: none");
+    } else {
+        println!("  Backups:");
+        for backup in backups {
+            println!(
+                "    - {}",
+                backup.file_name().unwrap_or_default().to_string_lossy()
+            );
+        }
+    }
+    Ok(())
+}
+
+fn boot_list(name: &str) -> Result<(), Error> {
+    let config = MachineConfig::load(name)?;
+    let store = VarStore::parse(fs::read(existing_vars(&config)?)?)?;
+    let order = store.boot_order();
+    let entries = store.boot_entries();
+    if entries.is_empty() {
+        println!("'{}' has no boot entries.", name);
+        return Ok(());
+    }
+
+    println!(
+        "Boot order: {}",
+        order
+            .iter()
+            .map(|id| format!("{:04X}", id))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    let ordered = order
+        .iter()
+        .filter_map(|id| entries.iter().find(|(entry, _)| entry == id))
+        .chain(entries.iter().filter(|(id, _)| !order.contains(id)));
+    for (id, option) in ordered {
+        println!(
+            "  {} {}{}{}",
+            boot_variable_name(*id),
+            option.description,
+            if option.active() { "" } else { " (inactive)" },
+            if order.contains(id) {
+                ""
+            } else {
+                " (not in boot order)"
+            }
+        );
+    }
+    Ok(())
+}
+
+fn boot_order(name: &str, requested: &[String]) -> Result<(), Error> {
+    edit_boot_entries(name, |store| {
+        let entries = store.boot_entries();
+        let mut order = Vec::new();
+        for value in requested {
+            let id = parse_boot_id(value)?;
+            if !entries.iter().any(|(entry, _)| *entry == id) {
+                return Err(format!("{} does not exist", boot_variable_name(id)));
+            }
+            if !order.contains(&id) {
+                order.push(id);
+            }
+        }
+        let rest: Vec<u16> = store
+            .boot_order()
+            .into_iter()
+            .filter(|id| !order.contains(id))
+            .collect();
+        order.extend(rest);
+        store.set_boot_order(&order);
+        Ok(format!(
+            "Boot order: {}",
+            order
+                .iter()
+                .map(|id| format!("{:04X}", id))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))
+    })
+}
+
+fn boot_delete(name: &str, entry: &str) -> Result<(), Error> {
+    edit_boot_entries(name, |store| {
+        let id = parse_boot_id(entry)?;
+        if !store.remove(&EFI_GLOBAL_VARIABLE, &boot_variable_name(id)) {
+            return Err(format!("{} does not exist", boot_variable_name(id)));
+        }
+        let order: Vec<u16> = store
+            .boot_order()
+            .into_iter()
+            .filter(|other| *other != id)
+            .collect();
+        store.set_boot_order(&order);
+        Ok(format!("Deleted {}", boot_variable_name(id)))
+    })
+}
+
+/// Applies `edit` to the machine's variable store after taking a backup.
+fn edit_boot_entries(
+    name: &str,
+    edit: impl FnOnce(&mut VarStore) -> Result<String, String>,
+) -> Result<(), Error> {
+    let config = MachineConfig::load(name)?;
+    let vars = existing_vars(&config)?;
+    ensure_not_in_use(name, &vars)?;
+
+    let mut store = VarStore::parse(fs::read(&vars)?)?;
+    let message = edit(&mut store)?;
+    let image = store.to_bytes()?;
+    let saved = backup(&config, &vars)?;
+    fs::write(&vars, image)?;
+    println!("{}", message);
+    println!("Previous NVRAM saved to {}", saved.display());
+    Ok(())
+}
+
+/// The machine's variable store, which must exist and be a raw image: the
+/// qcow2 stores some distributions ship can't be parsed.
+fn existing_vars(config: &MachineConfig) -> Result<PathBuf, Error> {
+    config.ensure_uefi()?;
+    let format = config.get("OVMF_FORMAT").unwrap_or("raw");
+    if format != "raw" {
+        return Err(Error::Io(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            format!(
+                "'{}' uses {} firmware, whose NVRAM can't be edited here. Change the boot entries in the firmware setup (press Esc while the VM starts) instead.",
+                config.name, format
+            ),
+        )));
+    }
+    let vars = config.vars_path()?;
+    if !vars.exists() {
+        return Err(Error::Io(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!(
+                "'{}' has no NVRAM yet; it is created on the first boot.",
+                config.name
+            ),
+        )));
+    }
+    Ok(vars)
+}
+
+/// Refuses to touch a variable store that a running QEMU has open, since the
+/// firmware writes to it at any time and would clobber our changes.
+pub fn ensure_not_in_use(name: &str, vars: &Path) -> Result<(), Error> {
+    let pids = processes_using(vars);
+    if pids.is_empty() {
+        return Ok(());
+    }
+    Err(Error::Io(std::io::Error::new(
+        std::io::ErrorKind::ResourceBusy,
+        format!(
+            "The NVRAM of '{}' is in use by PID {}. Shut the VM down first.",
+            name,
+            join_pids(&pids)
+        ),
+    )))
+}
+
+fn join_pids(pids: &[u32]) -> String {
+    pids.iter()
+        .map(|pid| pid.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Copies `vars` into the machine's backup directory under a timestamped name.
+pub fn backup(config: &MachineConfig, vars: &Path) -> Result<PathBuf, Error> {
+    let dir = config.nvram_backup_dir()?;
+    fs::create_dir_all(&dir)?;
+    let stamp = file_timestamp(SystemTime::now());
+    let mut target = dir.join(format!("{}.vars", stamp));
+    let mut counter = 1;
+    while target.exists() {
+        target = dir.join(format!("{}_{}.vars", stamp, counter));
+        counter += 1;
+    }
+    fs::copy(vars, &target)?;
+    Ok(target)
+}
+
+/// Backups of the machine's NVRAM, oldest first.
+fn list_backups(config: &MachineConfig) -> Result<Vec<PathBuf>, Error> {
+    let dir = config.nvram_backup_dir()?;
+    let Ok(entries) = fs::read_dir(&dir) else {
+        return Ok(Vec::new());
+    };
+    let mut backups: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().and_then(|e| e.to_str()) == Some("vars"))
+        .collect();
+    backups.sort();
+    Ok(backups)
+}
@@ -0,0 +1,117 @@
+use crate::modules::machine::MachineConfig;
+use crate::modules::network::{self, NetworkMode, PortForward};
+use crate::utils::error::Error;
+use clap::{Args, Subcommand};
+use std::net::IpAddr;
+
+#[derive(Args)]
+pub struct PortForwardCommand {
+    #[clap(subcommand)]
+    action: PortForwardAction,
+}
+
+#[derive(Subcommand)]
+enum PortForwardAction {
+    /// Forward a host port to the guest, e.g. `port-forward add my-vm adb 5555 5555`
+    Add {
+        name: String,
+        rule: String,
+        #[clap(value_parser = clap::value_parser!(u16).range(1..))]
+        host_port: u16,
+        #[clap(value_parser = clap::value_parser!(u16).range(1..))]
+        guest_port: u16,
+        #[clap(long)]
+        udp: bool,
+        /// Host address to listen on; 0.0.0.0 or :: exposes the port to the network
+        #[clap(long, default_value = "127.0.0.1")]
+        bind: IpAddr,
+    },
+    /// Stop forwarding a port
+    Remove { name: String, rule: String },
+    /// Show the forwarded ports
+    List { name: String },
+}
+
+impl PortForwardCommand {
+    pub fn exec(&self) -> Result<(), Error> {
+        match &self.action {
+            PortForwardAction::Add {
+                name,
+                rule,
+                host_port,
+                guest_port,
+                udp,
+                bind,
+            } => {
+                network::check_name(rule)?;
+                let forward = PortForward {
+                    name: rule.clone(),
+                    protocol: if *udp { "udp" } else { "tcp" }.to_string(),
+                    host_addr: *bind,
+                    host_port: *host_port,
+                    guest_port: *guest_port,
+                };
+                let mut config = MachineConfig::load(name)?;
+                let mut forwards = config.port_forwards()?;
+                forwards.retain(|other| other.name != forward.name);
+                if let Some(other) = forwards.iter().find(|other| other.conflicts_with(&forward)) {
+                    return Err(Error::Io(std::io::Error::new(
+                        std::io::ErrorKind::AlreadyExists,
+                        format!(
+                            "Host port {} is already forwarded by '{}'",
+                            forward.host_port, other.name
+                        ),
+                    )));
+                }
+                let host = forward.host();
+                forwards.push(forward);
+                config.set_port_forwards(&forwards);
+                config.save()?;
+                println!(
+                    "Forwarding {} to port {} of '{}' from the next start.",
+                    host, guest_port, name
+                );
+                if config.network_mode()? != NetworkMode::User {
+                    println!(
+                        "Note: port forwarding needs user-mode networking (`config set {} network user`).",
+                        name
+                    );
+                }
+            }
+            PortForwardAction::Remove { name, rule } => {
+                let mut config = MachineConfig::load(name)?;
+                let mut forwards = config.port_forwards()?;
+                let count = forwards.len();
+                forwards.retain(|forward| forward.name != *rule);
+                if forwards.len() == count {
+                    return Err(Error::Io(std::io::Error::new(
+                        std::io::ErrorKind::NotFound,
+                        format!("'{}' has no port forward named '{}'", name, rule),
+                    )));
+                }
+                config.set_port_forwards(&forwards);
+                config.save()?;
+                println!("Removed port forward '{}' from '{}'.", rule, name);
+            }
+            PortForwardAction::List { name } => {
+                let config = MachineConfig::load(name)?;
+                let forwards = config.port_forwards()?;
+                if forwards.is_empty() {
+                    println!("'{}' has no port forwards.", name);
+                    return Ok(());
+                }
+                println!("Port forwards of '{}':", name);
+                for forward in forwards {
+                    println!(
+                        "  {:<10} {} {} -> {}",
+                        forward.name,
+                        forward.protocol,
+                        forward.host(),
+                        forward.guest_port
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
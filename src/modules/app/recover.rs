@@ -1,76 +1,210 @@
+use crate::modules::app::image;
+use crate::modules::app::nvram;
 use crate::modules::app::run::run_qemu;
+use crate::modules::firmware::FirmwareType;
+use crate::modules::machine::MachineConfig;
+use crate::modules::qemu::{self, MediaAttach};
 use crate::utils::error::Error;
-use crate::utils::resource::ResourceValue;
+use crate::utils::process::processes_using;
+use crate::utils::progress::ProgressReader;
+use crate::utils::resource::parse_disk_size;
+use crate::utils::time::file_timestamp;
 use clap::Args;
-use dirs;
-use std::collections::HashMap;
-use std::fs;
-use std::str::FromStr;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
 #[derive(Args)]
 pub struct RecoverCommand {
     #[clap(long)]
     name: String,
+    #[clap(long, required_unless_present = "image", conflicts_with = "image")]
+    iso: Option<String>,
     #[clap(long)]
-    iso: String,
+    image: Option<String>,
+    #[clap(long)]
+    skip_check: bool,
+    #[clap(long, value_enum)]
+    media_attach: Option<MediaAttach>,
+    /// Erase the disk and reset the NVRAM before installing
+    #[clap(long)]
+    wipe: bool,
+    /// Keep the old disk as a timestamped backup when wiping
+    #[clap(long)]
+    backup: bool,
+    /// Do not ask for confirmation before wiping
+    #[clap(long)]
+    yes: bool,
 }
 
 impl RecoverCommand {
     pub fn exec(&self) -> Result<(), Error> {
-        println!("Recovering VM '{}' with ISO '{}'...", self.name, self.iso);
+        self.recover(self.wipe)
+    }
 
-        let config_dir = dirs::home_dir()
-            .ok_or_else(|| {
-                Error::Io(std::io::Error::new(
-                    std::io::ErrorKind::NotFound,
-                    "Home directory not found",
-                ))
-            })?
-            .join(".chromeos-launcher");
-        let machines_dir = config_dir.join("machines");
+    /// `reinstall`: `recover` that always wipes the disk first.
+    pub fn reinstall(&self) -> Result<(), Error> {
+        self.recover(true)
+    }
 
-        let config_file = machines_dir.join(&self.name);
-        if !config_file.exists() {
-            return Err(Error::Io(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                format!("Configuration for '{}' not found.", self.name),
+    fn recover(&self, wipe: bool) -> Result<(), Error> {
+        if self.backup && !wipe {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "--backup only applies together with --wipe",
             )));
         }
+        let media = self
+            .iso
+            .as_deref()
+            .or(self.image.as_deref())
+            .unwrap_or_default();
+        println!("Recovering VM '{}' with '{}'...", self.name, media);
 
-        let config_content = fs::read_to_string(&config_file)?;
-        let config: HashMap<_, _> = config_content
-            .lines()
-            .filter_map(|line| {
-                let mut parts = line.splitn(2, '=');
-                Some((
-                    parts.next()?.trim(),
-                    parts.next()?.trim().trim_matches('\''),
-                ))
-            })
-            .collect();
+        let mut config = MachineConfig::load(&self.name)?;
+        if let Some(media_attach) = self.media_attach {
+            config.set("MEDIA_ATTACH", media_attach);
+            config.save()?;
+        }
+        let iso = image::install_media(self.iso.as_deref(), self.image.as_deref())?;
+        if !self.skip_check {
+            image::check_for_machine(&config, media, &iso)?;
+        }
+        if wipe {
+            if !self.yes && !confirm_wipe(&config)? {
+                println!("Reinstall cancelled.");
+                return Ok(());
+            }
+            wipe_disk(&config, self.backup)?;
+            if config.firmware_type()? == FirmwareType::Uefi {
+                nvram::reset(&self.name)?;
+            }
+            config = MachineConfig::load(&self.name)?;
+        }
+        let recorded = match &self.iso {
+            Some(path) => fs::canonicalize(path)?.to_string_lossy().into_owned(),
+            None => iso.clone(),
+        };
+        if config.get("RECOVERY_PATH") != Some(recorded.as_str()) {
+            config.set("RECOVERY_PATH", &recorded);
+            config.save()?;
+            println!(
+                "Recorded {} as the recovery image of '{}'",
+                recorded, self.name
+            );
+        }
+        run_qemu(&mut config, "install", Some(iso), None)?;
 
-        let memory_str = config.get("MEMORY").unwrap_or(&"4G");
-        let cpu_cores_str = config.get("CPU_CORES").unwrap_or(&"2");
-        let cpu_model = config.get("CPU_MODEL").unwrap_or(&"host");
-        let disk_path = config.get("DISK_PATH").unwrap();
-        let ovmf_code_str = config.get("OVMF_CODE").map(|s| s.to_string());
+        Ok(())
+    }
+}
 
-        let memory = ResourceValue::from_str(memory_str)?;
-        let cpu_cores = ResourceValue::from_str(cpu_cores_str)?;
+fn confirm_wipe(config: &MachineConfig) -> Result<bool, Error> {
+    print!(
+        "This erases everything on '{}' and resets the NVRAM of '{}'. Continue? [y/N] ",
+        config.disk_path()?.display(),
+        config.name
+    );
+    io::stdout().flush()?;
+    let mut input = String::new();
+    io::stdin().read_line(&mut input)?;
+    Ok(input.trim().to_lowercase() == "y")
+}
 
-        run_qemu(
-            &self.name,
-            "install",
-            Some(self.iso.clone()),
-            None, // No recovery path in recover command
-            disk_path,
-            &cpu_cores,
-            &memory,
-            cpu_model,
-            ovmf_code_str,
-            false, // No 3D accel in recover command
-        )?;
+/// Refuses to replace a disk that a running QEMU has open.
+pub fn ensure_disk_not_in_use(config: &MachineConfig, disk: &Path) -> Result<(), Error> {
+    let pids = processes_using(disk);
+    if pids.is_empty() {
+        return Ok(());
+    }
+    Err(Error::Io(io::Error::new(
+        io::ErrorKind::ResourceBusy,
+        format!(
+            "The disk of '{}' is in use by PID {}. Shut the VM down first.",
+            config.name,
+            pids.iter()
+                .map(|pid| pid.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    )))
+}
 
-        Ok(())
+/// Replaces the machine's disk with an empty one of the configured size and
+/// format. Block devices cannot be recreated, so their partition tables are
+/// cleared instead.
+fn wipe_disk(config: &MachineConfig, backup: bool) -> Result<(), Error> {
+    let disk = config.disk_path()?;
+    ensure_disk_not_in_use(config, &disk)?;
+    let metadata = fs::metadata(&disk).ok();
+    let stamp = file_timestamp(SystemTime::now());
+
+    if metadata
+        .as_ref()
+        .is_some_and(|m| m.file_type().is_block_device())
+    {
+        if backup {
+            let dir = config.disk_backup_dir()?;
+            fs::create_dir_all(&dir)?;
+            let target = dir.join(format!("{}.img", stamp));
+            println!("Copying {} to {}...", disk.display(), target.display());
+            copy_device(&disk, &target)?;
+        }
+        println!("Clearing the partition tables on {}", disk.display());
+        return clear_partition_tables(&disk);
     }
+
+    let (size, size_label) = match (config.get("HDD_SIZE"), &metadata) {
+        (Some(size), _) => (parse_disk_size(size)?, size.to_string()),
+        (None, Some(metadata)) if config.disk_format() == "raw" => {
+            (metadata.len(), format!("{} bytes", metadata.len()))
+        }
+        (None, _) => (64 << 30, "64G".to_string()),
+    };
+    if metadata.is_some() {
+        if backup {
+            let mut target = disk.as_os_str().to_os_string();
+            target.push(format!(".{}.bak", stamp));
+            let target = PathBuf::from(target);
+            fs::rename(&disk, &target)?;
+            println!("Moved the old disk to {}", target.display());
+        } else {
+            fs::remove_file(&disk)?;
+        }
+    }
+    println!(
+        "Creating a new {} disk of {} at {}",
+        config.disk_format(),
+        size_label,
+        disk.display()
+    );
+    qemu::create_disk(&disk, config.disk_format(), size)
+}
+
+fn copy_device(source: &Path, target: &Path) -> Result<(), Error> {
+    let mut device = File::open(source)?;
+    let size = device.seek(SeekFrom::End(0))?;
+    device.seek(SeekFrom::Start(0))?;
+    let mut reader = ProgressReader::new(device, size);
+    let copied = io::copy(&mut reader, &mut File::create(target)?);
+    eprintln!();
+    copied?;
+    Ok(())
+}
+
+/// Zeroes the first and last MiB of a device, where the primary and backup
+/// GPT live.
+fn clear_partition_tables(device: &Path) -> Result<(), Error> {
+    let mut file = OpenOptions::new().write(true).open(device)?;
+    let size = file.seek(SeekFrom::End(0))?;
+    let zeros = vec![0u8; 1 << 20];
+    let length = zeros.len().min(size as usize);
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(&zeros[..length])?;
+    file.seek(SeekFrom::Start(size - length as u64))?;
+    file.write_all(&zeros[..length])?;
+    file.sync_all()?;
+    Ok(())
 }
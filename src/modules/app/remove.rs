@@ -1,8 +1,9 @@
+use crate::modules::machine::{self, MachineConfig};
 use crate::utils::error::Error;
 use clap::Args;
-use dirs;
 use std::fs;
 use std::io::{self, Write};
+use std::path::PathBuf;
 
 #[derive(Args)]
 pub struct RemoveCommand {
@@ -11,30 +12,21 @@ pub struct RemoveCommand {
 
 impl RemoveCommand {
     pub fn exec(&self) -> Result<(), Error> {
-        let config_dir = dirs::home_dir()
-            .ok_or_else(|| {
-                Error::Io(std::io::Error::new(
-                    std::io::ErrorKind::NotFound,
-                    "Home directory not found",
-                ))
-            })?
-            .join(".chromeos-launcher");
-        let machines_dir = config_dir.join("machines");
-        let config_file = machines_dir.join(&self.name);
+        let config = MachineConfig::load(&self.name)?;
+        // Machines without DISK_PATH keep their disk at machines/<name>.img.
+        let disk_path = config.disk_path()?;
+        // Recordings and disk backups are the user's data, not the launcher's.
+        let kept: Vec<PathBuf> = [config.audio_wav_path()?, config.disk_backup_dir()?]
+            .into_iter()
+            .filter(|path| path.exists())
+            .collect();
 
-        if !config_file.exists() {
-            return Err(Error::Io(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                format!("VM '{}' not found", self.name),
-            )));
+        if !kept.is_empty() {
+            println!("These files are kept; delete them by hand if no longer needed:");
+            for path in &kept {
+                println!("  {}", path.display());
+            }
         }
-
-        let config_content = fs::read_to_string(&config_file)?;
-        let disk_path_line = config_content
-            .lines()
-            .find(|line| line.starts_with("DISK_PATH="));
-        let disk_path = disk_path_line.map(|line| line.split('=').nth(1).unwrap().trim());
-
         print!(
             "Are you sure you want to remove the VM '{}'? [y/N] ",
             self.name
@@ -47,31 +39,39 @@ impl RemoveCommand {
             return Ok(());
         }
 
-        if let Some(disk_path) = disk_path {
-            if fs::metadata(disk_path).is_ok() {
-                print!(
-                    "Do you also want to delete the disk file '{}'? [y/N] ",
-                    disk_path
-                );
-                io::stdout().flush()?;
-                let mut input = String::new();
-                io::stdin().read_line(&mut input)?;
-                if input.trim().to_lowercase() == "y" {
-                    println!("Deleting disk file: {}", disk_path);
-                    fs::remove_file(disk_path)?;
-                }
+        // Only image files are offered for deletion, never block devices.
+        if disk_path.is_file() {
+            print!(
+                "Do you also want to delete the disk file '{}'? [y/N] ",
+                disk_path.display()
+            );
+            io::stdout().flush()?;
+            let mut input = String::new();
+            io::stdin().read_line(&mut input)?;
+            if input.trim().to_lowercase() == "y" {
+                println!("Deleting disk file: {}", disk_path.display());
+                fs::remove_file(&disk_path)?;
             }
         }
 
         println!("Deleting VM configuration for '{}'.", self.name);
-        fs::remove_file(config_file)?;
-
-        let ovmf_vars = machines_dir.join(format!("{}.vars", self.name));
-        if ovmf_vars.exists() {
-            fs::remove_file(ovmf_vars)?;
+        fs::remove_file(config.path())?;
+        for file in [
+            config.vars_path()?,
+            config.release_cache_path()?,
+            config.ssh_key_path()?,
+            config.debug_log_path()?,
+        ] {
+            if file.exists() {
+                fs::remove_file(file)?;
+            }
+        }
+        let nvram_backups = config.nvram_backup_dir()?;
+        if nvram_backups.exists() {
+            fs::remove_dir_all(nvram_backups)?;
         }
 
-        let last_run_file = config_dir.join("last_run");
+        let last_run_file = machine::config_dir()?.join("last_run");
         if last_run_file.exists() {
             let last_run_vm = fs::read_to_string(&last_run_file)?;
             if last_run_vm.trim() == self.name {
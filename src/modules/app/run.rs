@@ -1,30 +1,50 @@
-use crate::modules::qemu::{self};
+use crate::modules::app::image;
+use crate::modules::cache;
+use crate::modules::chromeos::{self, gpt};
+use crate::modules::firmware::FirmwareType;
+use crate::modules::machine::{self, MachineConfig};
+use crate::modules::network::{self, NetBackend};
+use crate::modules::qemu::{self, AudioBackend, DisplayBackend, Gpu, MediaAttach};
 use crate::utils::error::Error;
-use crate::utils::resource::ResourceValue;
+use crate::utils::resource::{ResourceValue, parse_disk_size};
 use clap::Args;
-use dirs;
-use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf; // Import PathBuf
+use std::path::Path;
 use std::process::{Command, Stdio};
 use std::str::FromStr;
+use std::time::{Duration, Instant};
 
 #[derive(Args)]
 pub struct RunCommand {
     name: Option<String>,
+    /// Start the installer from a recovery image (file or library id) instead
+    #[clap(long, value_name = "IMAGE")]
+    install: Option<String>,
+    #[clap(long)]
+    skip_check: bool,
+    /// Also attach the recovery image recorded by `recover`
+    #[clap(long, conflicts_with = "install")]
+    with_recovery: bool,
+    /// Boot this kernel directly instead of the one on the disk (this run only)
+    #[clap(long, conflicts_with = "install")]
+    kernel: Option<String>,
+    /// Show the guest on this display instead of the DISPLAY setting (this run only)
+    #[clap(long, value_enum)]
+    display: Option<DisplayBackend>,
+    /// Open QEMU's gdbstub on localhost (default port 1234)
+    #[clap(long, value_name = "PORT", num_args = 0..=1, require_equals = true, default_missing_value = "1234")]
+    gdb: Option<u16>,
+    /// Keep the CPUs stopped until a debugger continues them (implies --gdb)
+    #[clap(long)]
+    wait_for_debugger: bool,
+    /// Log QEMU debug items (`-d`, e.g. int,cpu_reset) to machines/<name>.log
+    #[clap(long, value_name = "ITEMS")]
+    debug_log: Option<String>,
 }
 
 impl RunCommand {
     pub fn exec(&self) -> Result<(), Error> {
-        let config_dir = dirs::home_dir()
-            .ok_or_else(|| {
-                Error::Io(std::io::Error::new(
-                    std::io::ErrorKind::NotFound,
-                    "Home directory not found",
-                ))
-            })?
-            .join(".chromeos-launcher");
-        let machines_dir = config_dir.join("machines");
+        let config_dir = machine::config_dir()?;
         let last_run_file = config_dir.join("last_run");
 
         let vm_name = match &self.name {
@@ -40,50 +60,39 @@ impl RunCommand {
             }
         };
 
-        let config_file = machines_dir.join(&vm_name);
-        if !config_file.exists() {
-            return Err(Error::Io(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                format!("Configuration for '{}' not found.", vm_name),
-            )));
-        }
-
-        let config_content = fs::read_to_string(&config_file)?;
-        let config: HashMap<_, _> = config_content
-            .lines()
-            .filter_map(|line| {
-                let mut parts = line.splitn(2, '=');
-                Some((
-                    parts.next()?.trim(),
-                    parts.next()?.trim().trim_matches('\''),
+        let mut config = MachineConfig::load(&vm_name)?;
+        let recovery_path = if self.with_recovery {
+            let recorded = config.get("RECOVERY_PATH").ok_or_else(|| {
+                Error::Io(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!(
+                        "No recovery image recorded for '{}'. Run `recover --name {} --iso <image>` first.",
+                        vm_name, vm_name
+                    ),
                 ))
-            })
-            .collect();
-
-        // Retrieve config values with defaults
-        let memory_str = config.get("MEMORY").unwrap_or(&"4G");
-        let cpu_cores_str = config.get("CPU_CORES").unwrap_or(&"2");
-        let cpu_model = config.get("CPU_MODEL").unwrap_or(&"host");
-        let disk_path_str = config.get("DISK_PATH").map(|s| s.to_string()); // Make disk_path optional for now to handle creation
-        let ovmf_code_str = config.get("OVMF_CODE").map(|s| s.to_string());
-        let recovery_path = config.get("RECOVERY_PATH").map(|s| s.to_string());
-        let use_3d_accel = config
-            .get("USE_3D_ACCEL")
-            .and_then(|s| s.parse::<bool>().ok())
-            .unwrap_or(false);
-        let hdd_size = config.get("HDD_SIZE").unwrap_or(&"50G"); // New: HDD Size for creation
-
-        let memory = ResourceValue::from_str(memory_str)?;
-        let cpu_cores = ResourceValue::from_str(cpu_cores_str)?;
-
-        // --- Start: Logic for disk image creation (similar to bash script) ---
-        let disk_path = if let Some(path) = disk_path_str {
-            PathBuf::from(path)
+            })?;
+            Some(cache::uncompressed(recorded)?)
         } else {
-            // Default disk path if not specified in config
-            config_dir.join("machines").join(&vm_name).join("image.img")
+            None
+        };
+        let hdd_size = config.get("HDD_SIZE").unwrap_or("50G");
+
+        let disk_path = config.disk_path()?;
+        let install_media = match &self.install {
+            Some(reference) => {
+                let media = image::media_reference(reference)?;
+                if !self.skip_check {
+                    image::check_for_machine(&config, reference, &media)?;
+                }
+                Some(media)
+            }
+            None => None,
         };
+        if install_media.is_none() && recovery_path.is_none() && !self.skip_check {
+            ensure_installed(&config, &disk_path)?;
+        }
 
+        // --- Start: Logic for disk image creation (similar to bash script) ---
         if !disk_path.exists() {
             println!("---");
             println!(
@@ -91,67 +100,332 @@ impl RunCommand {
                 disk_path.display()
             );
 
-            // Ensure the parent directory exists
-            if let Some(parent) = disk_path.parent() {
-                fs::create_dir_all(parent)?;
-            }
-
-            // Execute qemu-img create
-            let hdd_size_str = hdd_size; // Use the configured HDD size
-            let status = Command::new("qemu-img")
-                .args(&[
-                    "create",
-                    "-f",
-                    "raw",
-                    disk_path.to_str().unwrap(),
-                    hdd_size_str,
-                ])
-                .stdout(Stdio::inherit())
-                .stderr(Stdio::inherit())
-                .status()?;
-
-            if !status.success() {
-                return Err(Error::Io(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    format!("Failed to create disk image: {}", disk_path.display()),
-                )));
-            }
+            qemu::create_disk(&disk_path, config.disk_format(), parse_disk_size(hdd_size)?)?;
             println!("Disk image created successfully.");
             println!("---");
         }
         // --- End: Logic for disk image creation ---
 
-        run_qemu(
-            &vm_name,
-            "run",
-            None, // iso_path is handled by `install` mode, not `run` command
-            recovery_path,
-            &disk_path.to_string_lossy(), // Pass disk_path as &str
-            &cpu_cores,
-            &memory,
-            cpu_model,
-            ovmf_code_str,
-            use_3d_accel,
-        )?;
+        let mut options = LaunchOptions {
+            kernel: self.kernel.clone(),
+            display: self.display,
+        };
+        let mut launch = if install_media.is_some() {
+            options.kernel = None;
+            prepare_qemu(&mut config, "install", install_media, None, &options)?
+        } else {
+            prepare_qemu(
+                &mut config,
+                "run",
+                None, // iso_path is handled by `install` mode, not `run` command
+                recovery_path,
+                &options,
+            )?
+        };
+        self.add_debug_options(&config, &mut launch)?;
+        launch.run().map_err(|failure| failure.error)
+    }
 
+    /// Appends the gdbstub and debug log options and tells the user how to
+    /// attach.
+    fn add_debug_options(
+        &self,
+        config: &MachineConfig,
+        launch: &mut QemuLaunch,
+    ) -> Result<(), Error> {
+        let gdb_port = match self.gdb {
+            None if self.wait_for_debugger => Some(1234),
+            port => port,
+        };
+        if let Some(port) = gdb_port {
+            launch
+                .args
+                .extend(["-gdb".to_string(), format!("tcp:127.0.0.1:{}", port)]);
+            println!(
+                "GDB: gdb -ex 'target remote localhost:{}' (use vmlinux for kernel symbols)",
+                port
+            );
+        }
+        if self.wait_for_debugger {
+            launch.args.push("-S".to_string());
+            println!("The guest is paused until the debugger continues it (`continue` in gdb).");
+        }
+        if let Some(items) = &self.debug_log {
+            let log_path = config.debug_log_path()?;
+            launch.args.extend([
+                "-d".to_string(),
+                items.clone(),
+                "-D".to_string(),
+                log_path.display().to_string(),
+            ]);
+            println!("Debug log ({}): {}", items, log_path.display());
+        }
         Ok(())
     }
 }
 
+/// Refuses to boot a disk without ChromeOS on it, which would only drop into
+/// the firmware's boot menu or shell.
+fn ensure_installed(config: &MachineConfig, disk_path: &Path) -> Result<(), Error> {
+    let installed = if disk_path.exists() {
+        match config
+            .raw_disk_path()
+            .and_then(|raw| chromeos::is_installed(&raw))
+        {
+            Ok(installed) => installed,
+            Err(err) => {
+                println!(
+                    "Warning: could not inspect '{}' ({}); booting it anyway.",
+                    disk_path.display(),
+                    err.message()
+                );
+                return Ok(());
+            }
+        }
+    } else {
+        false
+    };
+    if installed {
+        return Ok(());
+    }
+    Err(Error::Io(std::io::Error::new(
+        std::io::ErrorKind::NotFound,
+        format!(
+            "ChromeOS is not installed on '{}'. Install it with `recover --name {} --iso <image>` or `run {} --install <image>` (use --skip-check to boot the disk anyway).",
+            disk_path.display(),
+            config.name,
+            config.name
+        ),
+    )))
+}
+
+/// Launches QEMU for the machine described by `config`.
+///
+/// In `install` mode `iso_path` is attached as the installer image; the
+/// machine's own settings (memory, CPUs, firmware, ...) come from `config`.
 pub fn run_qemu(
-    vm_name: &str,
+    config: &mut MachineConfig,
     mode: &str,
     iso_path: Option<String>,
     recovery_path: Option<String>,
-    disk_path: &str, // This is now always the path, not an optional
-    cpu_cores: &ResourceValue,
-    memory: &ResourceValue,
-    cpu_model: &str,
-    ovmf_code: Option<String>,
-    use_3d_accel: bool,
 ) -> Result<(), Error> {
+    prepare_qemu(
+        config,
+        mode,
+        iso_path,
+        recovery_path,
+        &LaunchOptions::default(),
+    )?
+    .run()
+    .map_err(|failure| failure.error)
+}
+
+/// A QEMU command line ready to be started.
+pub struct QemuLaunch {
+    binary: String,
+    args: Vec<String>,
+}
+
+/// The status QEMU exits with when it rejects its configuration or cannot
+/// set up the machine. QEMU also exits with it for some errors while the
+/// guest runs, so it only means the guest never started within
+/// [`STARTUP_WINDOW`].
+const QEMU_INIT_FAILURE: i32 = 1;
+
+/// How long QEMU takes at most to set up the machine before the firmware
+/// runs.
+const STARTUP_WINDOW: Duration = Duration::from_secs(5);
+
+pub struct LaunchFailure {
+    pub error: Error,
+    /// Whether QEMU failed before the guest could have started booting.
+    pub before_boot: bool,
+}
+
+impl QemuLaunch {
+    pub fn run(&self) -> Result<(), LaunchFailure> {
+        let started = Instant::now();
+        let status = Command::new(&self.binary)
+            .args(&self.args)
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::inherit())
+            .status()
+            .map_err(|err| LaunchFailure {
+                error: Error::Io(std::io::Error::new(
+                    err.kind(),
+                    format!("Failed to start {}: {}", self.binary, err),
+                )),
+                before_boot: true,
+            })?;
+
+        if !status.success() {
+            return Err(LaunchFailure {
+                error: Error::Io(std::io::Error::other(format!(
+                    "QEMU exited with an error. Exit code: {:?}",
+                    status.code()
+                ))),
+                before_boot: status.code() == Some(QEMU_INIT_FAILURE)
+                    && started.elapsed() < STARTUP_WINDOW,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// A kernel booted by QEMU itself rather than loaded by the firmware.
+struct DirectBoot {
+    kernel: String,
+    initrd: Option<String>,
+    append: String,
+}
+
+/// Reads the `KERNEL`, `INITRD` and `APPEND` settings, with `kernel`
+/// overriding `KERNEL`. Without `APPEND` the kernel mounts the disk's active
+/// root partition.
+fn direct_boot(config: &MachineConfig, kernel: Option<&str>) -> Result<Option<DirectBoot>, Error> {
+    let Some(kernel) = kernel.or(config.get("KERNEL")) else {
+        return Ok(None);
+    };
+    let initrd = config.get("INITRD");
+    for (key, path) in [("KERNEL", Some(kernel)), ("INITRD", initrd)] {
+        if let Some(path) = path
+            && !Path::new(path).is_file()
+        {
+            return Err(Error::Io(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!(
+                    "{} not found: {} (change it with `config set {} {} <file>`)",
+                    key,
+                    path,
+                    config.name,
+                    key.to_lowercase()
+                ),
+            )));
+        }
+    }
+    let append = match config.get("APPEND") {
+        Some(append) => append.to_string(),
+        None => {
+            let disk_path = config.disk_path()?;
+            let root = config
+                .raw_disk_path()
+                .ok()
+                .and_then(|raw| fs::File::open(raw).ok())
+                .and_then(|mut disk| gpt::read(&mut disk).ok())
+                .and_then(|gpt| chromeos::active_root(&gpt).map(|root| root.partuuid()));
+            let Some(root) = root else {
+                return Err(Error::Io(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!(
+                        "Could not find the root partition on '{}'; set the kernel command line with `config set {} append <cmdline>`.",
+                        disk_path.display(),
+                        config.name
+                    ),
+                )));
+            };
+            // The device name depends on the arch and disk bus; the GPT's
+            // partition GUID does not.
+            format!("root=PARTUUID={} rootwait ro", root)
+        }
+    };
+    Ok(Some(DirectBoot {
+        kernel: kernel.to_string(),
+        initrd: initrd.map(str::to_string),
+        append,
+    }))
+}
+
+/// Settings overridden for a single launch.
+#[derive(Default)]
+pub struct LaunchOptions {
+    /// Boot this kernel directly, like the `KERNEL` setting; ignored in
+    /// `install` mode.
+    pub kernel: Option<String>,
+    pub display: Option<DisplayBackend>,
+}
+
+/// Validates the machine and builds its QEMU command line; see [`run_qemu`].
+pub fn prepare_qemu(
+    config: &mut MachineConfig,
+    mode: &str,
+    iso_path: Option<String>,
+    recovery_path: Option<String>,
+    options: &LaunchOptions,
+) -> Result<QemuLaunch, Error> {
     let qemu_config = qemu::detect_arch()?;
+    let secure_boot = config.get_bool("SECURE_BOOT");
+    let direct_boot = match mode {
+        "install" => None,
+        _ => direct_boot(config, options.kernel.as_deref())?,
+    };
+    let display = match options.display {
+        Some(display) => display,
+        None => config.display()?,
+    };
+    qemu_config.check_display(display)?;
+    let display_gl = display.has_gl(config.display_gl());
+    let gpu = match config.gpu()? {
+        Some(gpu) if gpu.needs_gl() && !display_gl => {
+            return Err(Error::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "The {} GPU needs an OpenGL display, but '{}' uses {}{}. Use sdl, gtk, spice-app or egl-headless, or `config set {} gpu virtio`.",
+                    gpu,
+                    config.name,
+                    display,
+                    if display.has_gl(true) {
+                        " with DISPLAY_GL=false"
+                    } else {
+                        ""
+                    },
+                    config.name
+                ),
+            )));
+        }
+        Some(gpu) => gpu,
+        None => Gpu::default_for(display_gl),
+    };
+    qemu_config.check_gpu(gpu)?;
+    let audio = config.audio()?;
+    qemu_config.check_audio(audio)?;
+    let audio_device = config.audio_device()?;
+    let audio_input = config.get_bool("AUDIO_INPUT");
+    let vnc_display = config
+        .get("VNC_DISPLAY")
+        .unwrap_or("127.0.0.1:0")
+        .to_string();
+    let firmware = match config.firmware_type()? {
+        // QEMU loads a direct-boot kernel itself, so no firmware is attached.
+        _ if direct_boot.is_some() => None,
+        FirmwareType::Uefi => Some(config.firmware(&qemu_config)?),
+        FirmwareType::Bios => {
+            if qemu_config.arch != "x86_64" {
+                return Err(Error::Io(std::io::Error::new(
+                    std::io::ErrorKind::Unsupported,
+                    "Legacy BIOS boot is only available on x86_64 hosts.",
+                )));
+            }
+            if secure_boot {
+                return Err(Error::Io(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!(
+                        "'{}' boots with legacy BIOS, which does not support Secure Boot.",
+                        config.name
+                    ),
+                )));
+            }
+            None
+        }
+    };
 
+    let vm_name = config.name.as_str();
+    let disk_path = config.disk_path()?;
+    let disk_path = disk_path.display();
+    let memory = ResourceValue::from_str(config.get("MEMORY").unwrap_or("4G"))?;
+    let cpu_cores = ResourceValue::from_str(config.get("CPU_CORES").unwrap_or("2"))?;
+    let cpu_model = config.get("CPU_MODEL").unwrap_or("host");
+    let nic_model = config.nic_model()?;
+    let net_backend = config.net_backend()?;
     let total_mem_kb = sys_info::mem_info()
         .map_err(|e| {
             Error::Io(std::io::Error::other(format!(
@@ -167,27 +441,27 @@ pub fn run_qemu(
         )))
     })? as u64;
 
-    let resolved_mem = qemu::resolve_value(memory, total_mem_kb, Some("G"));
-    let resolved_cores = qemu::resolve_value(cpu_cores, total_cores, None);
+    let resolved_mem = qemu::resolve_value(&memory, total_mem_kb, Some("G"));
+    let resolved_cores = qemu::resolve_value(&cpu_cores, total_cores, None);
 
-    let config_dir = dirs::home_dir()
-        .ok_or_else(|| {
-            Error::Io(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                "Home directory not found",
-            ))
-        })?
-        .join(".chromeos-launcher");
+    let config_dir = machine::config_dir()?;
     let last_run_file = config_dir.join("last_run");
 
     let mut qemu_args = Vec::<String>::new();
 
+    let media_attach = config.media_attach()?;
+    if media_attach == MediaAttach::IdeCd && qemu_config.arch != "x86_64" {
+        return Err(Error::Io(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "ide-cd media is only available on x86_64 hosts; use usb or virtio.",
+        )));
+    }
+    if iso_path.is_some() || recovery_path.is_some() {
+        qemu_args.extend(media_attach.controller_args());
+    }
     if mode == "install" {
         if let Some(ref iso) = iso_path {
-            qemu_args.extend(vec![
-                "-drive".to_string(),
-                format!("format=raw,file={}", iso),
-            ]);
+            qemu_args.extend(media_attach.drive_args("install", iso));
         } else {
             return Err(Error::Io(std::io::Error::new(
                 std::io::ErrorKind::InvalidInput,
@@ -195,75 +469,80 @@ pub fn run_qemu(
             )));
         }
     }
-    let machines_dir = config_dir.join("machines");
-    let ovmf_vars_copy = machines_dir.join(format!("{}.vars", vm_name));
-    if !ovmf_vars_copy.exists() {
-        if qemu_config.ovmf_vars_template.exists() {
-            fs::copy(&qemu_config.ovmf_vars_template, &ovmf_vars_copy)?;
-        } else {
-            return Err(Error::Io(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                format!(
-                    "OVMF_VARS.fd template not found at {}",
-                    qemu_config.ovmf_vars_template.display()
-                ),
-            )));
+    if let Some(firmware) = &firmware {
+        let ovmf_vars_copy = config.vars_path()?;
+        if !ovmf_vars_copy.exists() {
+            fs::copy(&firmware.vars_template, &ovmf_vars_copy)?;
         }
-    }
 
-    let ovmf_code_path = if let Some(path) = ovmf_code {
-        PathBuf::from(path)
-    } else {
-        qemu_config.ovmf_code_path.clone()
-    };
+        if secure_boot && qemu_config.arch == "x86_64" {
+            // Secure Boot builds of OVMF keep the variable store in SMM-protected flash.
+            qemu_args.extend(vec![
+                "-machine".to_string(),
+                "q35,smm=on".to_string(),
+                "-global".to_string(),
+                "driver=cfi.pflash01,property=secure,value=on".to_string(),
+            ]);
+        }
+
+        qemu_args.extend(vec![
+            "-drive".to_string(),
+            format!(
+                "if=pflash,format={},readonly=on,file={}",
+                firmware.format,
+                firmware.code.display()
+            ),
+            "-drive".to_string(),
+            format!(
+                "if=pflash,format={},file={}",
+                firmware.format,
+                ovmf_vars_copy.display()
+            ),
+        ]);
+    }
 
+    qemu_args.extend(display.args(
+        config.display_gl(),
+        config.get_bool("FULL_SCREEN"),
+        &vnc_display,
+    ));
+    // QEMU shows -name in the window title; commas are escaped by doubling.
+    let title = config.get("WINDOW_TITLE").unwrap_or(vm_name);
+    qemu_args.extend(vec!["-name".to_string(), title.replace(',', ",,")]);
     qemu_args.extend(vec![
-        "-drive".to_string(),
-        format!(
-            "if=pflash,format=raw,readonly=on,file={}",
-            ovmf_code_path.display()
-        ),
-        "-drive".to_string(),
-        format!("if=pflash,format=raw,file={}", ovmf_vars_copy.display()),
-        "-display".to_string(),
-        "sdl,show-cursor=on,gl=on".to_string(),
         "-usb".to_string(),
         "-device".to_string(),
         "usb-tablet".to_string(),
     ]);
 
-    if use_3d_accel {
-        qemu_args.extend(vec!["-vga".to_string(), "virtio".to_string()]);
-    } else {
-        let (xres, yres) = (1280, 800);
-        qemu_args.extend(vec![
-            "-device".to_string(),
-            format!("virtio-vga-gl,xres={},yres={}", xres, yres),
-        ]);
-    }
+    let (xres, yres) = config.resolution()?;
+    let max_outputs = config.get("MAX_OUTPUTS").unwrap_or("1");
+    qemu_args.extend(gpu.args((xres, yres), max_outputs, &resolved_mem));
 
-    if let Some(rec_path) = recovery_path {
-        qemu_args.push("-drive".to_string());
-        qemu_args.push(format!("format=raw,file={}", rec_path));
+    if let Some(rec_path) = &recovery_path {
+        qemu_args.extend(media_attach.drive_args("recovery", rec_path));
     }
 
     qemu_args.extend(vec![
         "-drive".to_string(),
-        format!("format=raw,file={}", disk_path),
+        format!("format={},file={}", config.disk_format(), disk_path),
         "-m".to_string(),
         resolved_mem.clone(),
         "-enable-kvm".to_string(),
         "-smp".to_string(),
         resolved_cores.clone(),
-        "-audiodev".to_string(),
-        "sdl,id=audio0".to_string(),
-        "-device".to_string(),
-        "intel-hda".to_string(),
-        "-device".to_string(),
-        "hda-output,audiodev=audio0".to_string(),
-        "-cpu".to_string(),
-        cpu_model.to_string(),
     ]);
+    qemu_args.extend(audio.args(&config.audio_wav_path()?));
+    qemu_args.extend(audio_device.args(audio_input));
+    qemu_args.extend(vec!["-cpu".to_string(), cpu_model.to_string()]);
+    if let Some(boot) = &direct_boot {
+        qemu_args.extend(vec!["-kernel".to_string(), boot.kernel.clone()]);
+        if let Some(initrd) = &boot.initrd {
+            qemu_args.extend(vec!["-initrd".to_string(), initrd.clone()]);
+        }
+        qemu_args.extend(vec!["-append".to_string(), boot.append.clone()]);
+    }
+    qemu_args.extend(network::args(&net_backend, nic_model));
 
     println!("---");
     println!("Starting QEMU for '{}'...", vm_name);
@@ -271,36 +550,124 @@ pub fn run_qemu(
     println!("  Memory: {}", resolved_mem);
     println!("  CPU Cores: {}", resolved_cores);
     println!("  Disk: {}", disk_path);
+    match display {
+        DisplayBackend::Vnc => println!("  Display: vnc ({})", vnc_display),
+        _ => println!("  Display: {}", display),
+    }
+    println!("  GPU: {}", gpu);
+    match audio {
+        AudioBackend::Wav => println!(
+            "  Audio: {} to {}",
+            audio_device,
+            config.audio_wav_path()?.display()
+        ),
+        _ => println!("  Audio: {} via {}", audio_device, audio),
+    }
+    if audio_input {
+        println!("  Microphone: Enabled");
+    }
+    match max_outputs {
+        "1" => println!("  Resolution: {}x{}", xres, yres),
+        _ => println!("  Resolution: {}x{} ({} outputs)", xres, yres, max_outputs),
+    }
 
     if mode != "install" {
-        println!("  UEFI Code: {}", qemu_config.ovmf_code_path.display());
-        if use_3d_accel {
-            println!("  3D Acceleration: Enabled");
+        match &firmware {
+            Some(firmware) => println!("  UEFI Code: {}", firmware.code.display()),
+            None if direct_boot.is_some() => {}
+            None => println!("  Firmware: Legacy BIOS"),
+        }
+        if secure_boot && direct_boot.is_none() {
+            println!("  Secure Boot: Enabled");
+        }
+        if let Some(boot) = &direct_boot {
+            println!("  Kernel: {}", boot.kernel);
+            if let Some(initrd) = &boot.initrd {
+                println!("  Initrd: {}", initrd);
+            }
+            println!("  Append: {}", boot.append);
+        }
+    }
+    match &net_backend {
+        NetBackend::User(port_forwards) => {
+            println!("  Network: user ({})", nic_model);
+            for forward in port_forwards {
+                println!(
+                    "  Forward {}: {} {} -> {}",
+                    forward.name,
+                    forward.protocol,
+                    forward.host(),
+                    forward.guest_port
+                );
+            }
+            if let Some(ssh) = port_forwards.iter().find(|forward| forward.name == "ssh") {
+                match config.get("SSH_KEY") {
+                    Some(key) => {
+                        println!("  SSH: ssh -i {} -p {} root@localhost", key, ssh.host_port)
+                    }
+                    None => println!("  SSH: ssh -p {} root@localhost", ssh.host_port),
+                }
+            }
         }
+        NetBackend::Bridge { name, .. } => println!("  Network: bridge {} ({})", name, nic_model),
+        NetBackend::Tap { name } => println!("  Network: tap {} ({})", name, nic_model),
+        NetBackend::None => println!("  Network: none"),
+    }
+    if !matches!(net_backend, NetBackend::User(_)) && !config.port_forwards()?.is_empty() {
+        println!("  Note: port forwards only apply to user-mode networking");
     }
     if let Some(iso) = iso_path.as_ref() {
-        println!("  ISO (Install Mode): {}", iso);
+        println!("  ISO (Install Mode): {} ({})", iso, media_attach);
+    }
+    if let Some(rec_path) = recovery_path.as_ref() {
+        println!("  Recovery Media: {} ({})", rec_path, media_attach);
     }
     println!("---");
 
     fs::write(&last_run_file, vm_name)?;
 
-    let qemu_args_str: Vec<&str> = qemu_args.iter().map(|s| s.as_str()).collect();
-    let qemu_command = Command::new(&qemu_config.binary)
-        .args(&qemu_args_str)
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
-        .status()?;
+    Ok(QemuLaunch {
+        binary: qemu_config.binary,
+        args: qemu_args,
+    })
+}
 
-    if !qemu_command.success() {
-        return Err(Error::Io(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            format!(
-                "QEMU exited with an error. Exit code: {:?}",
-                qemu_command.code()
-            ),
-        )));
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::chromeos::tests::recovery_image;
+
+    #[test]
+    fn test_ensure_installed_qcow2() {
+        let dir = std::env::temp_dir().join(format!(
+            "chromeos-launcher-installed-{}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let raw = dir.join("raw.img");
+        fs::write(&raw, recovery_image("CHROMEOS_RELEASE_BOARD=reven\n")).unwrap();
+        let qcow2 = dir.join("cros.qcow2");
+        // A qcow2 header; the guest's partition table is not at a fixed offset.
+        let mut image = b"QFI\xfb\0\0\0\x03".to_vec();
+        image.resize(64 * 1024, 0);
+        fs::write(&qcow2, image).unwrap();
 
-    Ok(())
+        let machine = |disk: &Path, format: &str| {
+            MachineConfig::from_content(
+                "cros",
+                &format!("DISK_PATH={}\nDISK_FORMAT={}\n", disk.display(), format),
+            )
+        };
+        let raw_machine = machine(&raw, "raw");
+        assert!(ensure_installed(&raw_machine, &raw).is_ok());
+        let qcow2_machine = machine(&qcow2, "qcow2");
+        assert!(ensure_installed(&qcow2_machine, &qcow2).is_ok());
+        // Read as raw, the same file has no ChromeOS on it.
+        assert!(ensure_installed(&machine(&qcow2, "raw"), &qcow2).is_err());
+        // A qcow2 disk that doesn't exist yet is still empty.
+        let missing = dir.join("new.qcow2");
+        assert!(ensure_installed(&machine(&missing, "qcow2"), &missing).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
 }
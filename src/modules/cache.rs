@@ -0,0 +1,301 @@
+use crate::modules::machine::config_dir;
+use crate::utils::error::Error;
+use crate::utils::progress::ProgressReader;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Compressed formats recovery images are distributed in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Compression {
+    Zip,
+    Gzip,
+    Xz,
+}
+
+impl Compression {
+    fn detect(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "zip" => Some(Compression::Zip),
+            "gz" => Some(Compression::Gzip),
+            "xz" => Some(Compression::Xz),
+            _ => None,
+        }
+    }
+}
+
+/// Returns `~/.chromeos-launcher/cache`, where decompressed images are kept.
+pub fn cache_dir() -> Result<PathBuf, Error> {
+    Ok(config_dir()?.join("cache"))
+}
+
+/// Returns a path QEMU can boot for `path`: the path itself for plain images,
+/// or the cached extraction of a `.zip`, `.gz` or `.xz` archive, extracting it
+/// first if the cache has no copy of this exact archive yet.
+pub fn uncompressed(path: &str) -> Result<String, Error> {
+    let source = Path::new(path);
+    let Some(compression) = Compression::detect(source) else {
+        return Ok(path.to_string());
+    };
+    let dir = cache_dir()?.join(cache_key(source)?);
+    if let Some(cached) = cached_image(&dir)? {
+        println!("Using cached extraction {}", cached.display());
+        return Ok(cached.to_string_lossy().into_owned());
+    }
+
+    fs::create_dir_all(&dir)?;
+    println!("Extracting {} into {}", path, dir.display());
+    let extracted = extract(source, compression, &dir)?;
+    Ok(extracted.to_string_lossy().into_owned())
+}
+
+/// Identifies an archive by its location, size and modification time, so a
+/// re-downloaded file with the same name is extracted again.
+fn cache_key(source: &Path) -> Result<String, Error> {
+    let canonical = fs::canonicalize(source)?;
+    let metadata = fs::metadata(&canonical)?;
+    let modified = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    // FNV-1a; only needs to be stable, not cryptographic.
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    let identity = format!("{}\0{}\0{}", canonical.display(), metadata.len(), modified);
+    for byte in identity.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    let stem = source
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("image");
+    Ok(format!("{}-{:016x}", stem, hash))
+}
+
+/// The finished extraction in `dir`, ignoring leftovers of an interrupted run.
+fn cached_image(dir: &Path) -> Result<Option<PathBuf>, Error> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Ok(None);
+    };
+    Ok(entries
+        .flatten()
+        .map(|entry| entry.path())
+        .find(|path| path.is_file() && path.extension().is_none_or(|e| e != "partial")))
+}
+
+fn extract(source: &Path, compression: Compression, dir: &Path) -> Result<PathBuf, Error> {
+    let file = File::open(source)?;
+    let total = file.metadata()?.len();
+    let (name, result) = match compression {
+        Compression::Zip => {
+            let mut archive = zip::ZipArchive::new(file).map_err(invalid_archive)?;
+            let index = image_entry(&mut archive)?;
+            let entry = archive.by_index(index).map_err(invalid_archive)?;
+            let name = entry
+                .enclosed_name()
+                .and_then(|p| p.file_name().map(|n| n.to_os_string()))
+                .ok_or_else(|| invalid_archive("unsafe file name in archive"))?;
+            let size = entry.size();
+            let target = dir.join(&name);
+            let reader = ProgressReader::new(entry, size);
+            let result = write_partial(&target, reader, |mut reader, writer| {
+                std::io::copy(&mut reader, writer).map(|_| ())
+            });
+            (name, result)
+        }
+        Compression::Gzip | Compression::Xz => {
+            let name = source.file_stem().unwrap_or_default().to_os_string();
+            let target = dir.join(&name);
+            let reader = BufReader::new(ProgressReader::new(file, total));
+            let result = write_partial(&target, reader, |mut reader, writer| {
+                if compression == Compression::Gzip {
+                    let mut decoder = flate2::bufread::MultiGzDecoder::new(reader);
+                    return std::io::copy(&mut decoder, writer).map(|_| ());
+                }
+                lzma_rs::xz_decompress(&mut reader, writer)
+                    .map_err(|err| std::io::Error::new(ErrorKind::InvalidData, err.to_string()))
+            });
+            (name, result)
+        }
+    };
+    eprintln!();
+    result?;
+    Ok(dir.join(name))
+}
+
+/// Decompresses into `<target>.partial` and only renames it into place once
+/// the whole image has been written.
+fn write_partial<R>(
+    target: &Path,
+    reader: R,
+    decompress: impl FnOnce(R, &mut BufWriter<File>) -> std::io::Result<()>,
+) -> Result<(), Error> {
+    let mut partial = target.as_os_str().to_os_string();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+
+    let mut writer = BufWriter::new(File::create(&partial)?);
+    let result = decompress(reader, &mut writer).and_then(|_| writer.flush());
+    drop(writer);
+    if let Err(err) = result {
+        let _ = fs::remove_file(&partial);
+        return Err(Error::Io(std::io::Error::new(
+            err.kind(),
+            format!("Failed to decompress {}: {}", target.display(), err),
+        )));
+    }
+    fs::rename(&partial, target)?;
+    Ok(())
+}
+
+/// Picks the disk image out of a zip: the largest `.bin`, or the largest file
+/// if there is none.
+fn image_entry(archive: &mut zip::ZipArchive<File>) -> Result<usize, Error> {
+    let mut best: Option<(bool, u64, usize)> = None;
+    for index in 0..archive.len() {
+        let entry = archive.by_index(index).map_err(invalid_archive)?;
+        if !entry.is_file() {
+            continue;
+        }
+        let is_bin = entry.name().to_ascii_lowercase().ends_with(".bin");
+        let candidate = (is_bin, entry.size(), index);
+        if best.is_none_or(|b| (candidate.0, candidate.1) > (b.0, b.1)) {
+            best = Some(candidate);
+        }
+    }
+    best.map(|(_, _, index)| index)
+        .ok_or_else(|| invalid_archive("archive contains no files"))
+}
+
+fn invalid_archive(err: impl std::fmt::Display) -> Error {
+    Error::Io(std::io::Error::new(
+        ErrorKind::InvalidData,
+        format!("Invalid archive: {}", err),
+    ))
+}
+
+/// A cached extraction with its size in bytes.
+pub struct CacheEntry {
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+pub fn entries() -> Result<Vec<CacheEntry>, Error> {
+    let Ok(dirs) = fs::read_dir(cache_dir()?) else {
+        return Ok(Vec::new());
+    };
+    let mut entries = Vec::new();
+    for dir in dirs.flatten() {
+        for file in fs::read_dir(dir.path())?.flatten() {
+            entries.push(CacheEntry {
+                size: file.metadata()?.len(),
+                path: file.path(),
+            });
+        }
+    }
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(entries)
+}
+
+/// Removes every cached extraction and returns the number of bytes freed.
+pub fn clean() -> Result<u64, Error> {
+    let freed = entries()?.iter().map(|entry| entry.size).sum();
+    let dir = cache_dir()?;
+    if dir.exists() {
+        fs::remove_dir_all(&dir)?;
+    }
+    Ok(freed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "chromeos-launcher-cache-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn image() -> Vec<u8> {
+        (0..200_000u32).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(
+            Compression::detect(Path::new("a.bin.zip")),
+            Some(Compression::Zip)
+        );
+        assert_eq!(
+            Compression::detect(Path::new("a.bin.XZ")),
+            Some(Compression::Xz)
+        );
+        assert_eq!(Compression::detect(Path::new("a.bin")), None);
+    }
+
+    #[test]
+    fn test_extract_zip() {
+        let dir = temp_dir("zip");
+        let source = dir.join("chromeos.bin.zip");
+        let mut writer = zip::ZipWriter::new(File::create(&source).unwrap());
+        let options = zip::write::SimpleFileOptions::default();
+        writer.start_file("README.txt", options).unwrap();
+        writer.write_all(&[b'x'; 300_000]).unwrap();
+        writer.start_file("chromeos.bin", options).unwrap();
+        writer.write_all(&image()).unwrap();
+        writer.finish().unwrap();
+
+        let out = dir.join("out");
+        fs::create_dir_all(&out).unwrap();
+        let extracted = extract(&source, Compression::Zip, &out).unwrap();
+        assert_eq!(extracted, out.join("chromeos.bin"));
+        assert_eq!(fs::read(&extracted).unwrap(), image());
+        assert_eq!(cached_image(&out).unwrap(), Some(extracted));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_extract_gzip_and_xz() {
+        let dir = temp_dir("stream");
+        let gz = dir.join("chromeos.bin.gz");
+        let mut encoder =
+            flate2::write::GzEncoder::new(File::create(&gz).unwrap(), flate2::Compression::fast());
+        encoder.write_all(&image()).unwrap();
+        encoder.finish().unwrap();
+        let xz = dir.join("flex.bin.xz");
+        let mut compressed = Vec::new();
+        lzma_rs::xz_compress(&mut &image()[..], &mut compressed).unwrap();
+        fs::write(&xz, compressed).unwrap();
+
+        for (source, compression, name) in [
+            (gz, Compression::Gzip, "chromeos.bin"),
+            (xz, Compression::Xz, "flex.bin"),
+        ] {
+            let extracted = extract(&source, compression, &dir).unwrap();
+            assert_eq!(extracted, dir.join(name));
+            assert_eq!(fs::read(&extracted).unwrap(), image());
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_corrupt_archive_leaves_no_image() {
+        let dir = temp_dir("corrupt");
+        let source = dir.join("broken.bin.gz");
+        fs::write(&source, b"not gzip at all").unwrap();
+        let out = dir.join("out");
+        fs::create_dir_all(&out).unwrap();
+        assert!(extract(&source, Compression::Gzip, &out).is_err());
+        assert_eq!(cached_image(&out).unwrap(), None);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
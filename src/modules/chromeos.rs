@@ -0,0 +1,302 @@
+use crate::utils::error::Error;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{ErrorKind, Seek, SeekFrom};
+use std::path::Path;
+
+pub mod extfs;
+pub mod gpt;
+
+use extfs::ExtFs;
+use gpt::{Gpt, Partition};
+
+/// Partitions every ChromeOS recovery image and installed disk has.
+const REQUIRED_PARTITIONS: [&str; 3] = ["STATE", "KERN-A", "ROOT-A"];
+
+/// The interesting parts of `/etc/lsb-release` from a ChromeOS root filesystem.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct LsbRelease {
+    pub name: String,
+    pub board: String,
+    pub version: String,
+    pub channel: String,
+    pub milestone: String,
+}
+
+/// Board name without the signing suffix, e.g. `reven` for
+/// `reven-signed-mp-v2keys`.
+pub fn short_board(board: &str) -> &str {
+    board
+        .split_once("-signed")
+        .map_or(board, |(board, _)| board)
+}
+
+impl LsbRelease {
+    pub fn parse(content: &str) -> Self {
+        let mut release = LsbRelease::default();
+        for line in content.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let value = value.trim().to_string();
+            match key.trim() {
+                "CHROMEOS_RELEASE_NAME" => release.name = value,
+                "CHROMEOS_RELEASE_BOARD" => release.board = value,
+                "CHROMEOS_RELEASE_VERSION" => release.version = value,
+                "CHROMEOS_RELEASE_TRACK" => release.channel = value,
+                "CHROMEOS_RELEASE_CHROME_MILESTONE" => release.milestone = value,
+                _ => {}
+            }
+        }
+        release
+    }
+
+    /// Board name without the signing suffix, see [`short_board`].
+    pub fn short_board(&self) -> &str {
+        short_board(&self.board)
+    }
+
+    /// Channel without the `-channel` suffix, e.g. `stable`.
+    pub fn short_channel(&self) -> &str {
+        self.channel
+            .strip_suffix("-channel")
+            .unwrap_or(&self.channel)
+    }
+}
+
+pub struct ImageInfo {
+    pub size: u64,
+    pub gpt: Gpt,
+    pub release: LsbRelease,
+}
+
+fn invalid(path: &Path, message: impl std::fmt::Display) -> Error {
+    Error::Io(std::io::Error::new(
+        ErrorKind::InvalidData,
+        format!("{}: {}", path.display(), message),
+    ))
+}
+
+/// Reads `/etc/lsb-release` from the filesystem in `partition`.
+pub fn read_lsb_release(file: &mut File, partition: &Partition) -> Result<LsbRelease, String> {
+    let mut fs = ExtFs::open(file, partition.offset())?;
+    let content = fs.read_file("/etc/lsb-release")?;
+    Ok(LsbRelease::parse(&String::from_utf8_lossy(&content)))
+}
+
+/// Checks that `path` is a complete ChromeOS recovery image and reads its
+/// release information from ROOT-A.
+pub fn inspect_image(path: &Path) -> Result<ImageInfo, Error> {
+    let mut file = File::open(path)?;
+    // Seeking works for block devices too, where the metadata length is 0.
+    let size = file.seek(SeekFrom::End(0))?;
+    let gpt = gpt::read(&mut file).map_err(|err| invalid(path, err))?;
+
+    if let Some(partition) = gpt.partitions.iter().find(|p| p.end() > size) {
+        return Err(invalid(
+            path,
+            format!(
+                "image is truncated: partition {} ({}) ends at byte {} but the file is only {} bytes",
+                partition.number,
+                partition.name,
+                partition.end(),
+                size
+            ),
+        ));
+    }
+    let missing: Vec<&str> = REQUIRED_PARTITIONS
+        .iter()
+        .copied()
+        .filter(|name| gpt.find(name).is_none())
+        .collect();
+    if !missing.is_empty() {
+        return Err(invalid(
+            path,
+            format!(
+                "not a ChromeOS image: missing partition(s) {}",
+                missing.join(", ")
+            ),
+        ));
+    }
+
+    let root = gpt.find("ROOT-A").unwrap();
+    let release = read_lsb_release(&mut file, root).map_err(|err| {
+        invalid(
+            path,
+            format!("cannot read /etc/lsb-release from ROOT-A: {}", err),
+        )
+    })?;
+
+    Ok(ImageInfo { size, gpt, release })
+}
+
+/// The root filesystem the firmware would boot: the one paired with the
+/// highest-priority kernel that is still bootable (marked successful or with
+/// tries left), preferring the earlier partition on ties like vboot does.
+/// Falls back to ROOT-A when no kernel is marked bootable.
+pub fn active_root(gpt: &Gpt) -> Option<&Partition> {
+    let mut best: Option<&Partition> = None;
+    for kernel in gpt
+        .partitions
+        .iter()
+        .filter(|p| p.type_guid == gpt::CHROMEOS_KERNEL_TYPE)
+        .filter(|p| p.priority() > 0 && (p.successful() || p.tries() > 0))
+    {
+        if best.is_none_or(|b| kernel.priority() > b.priority()) {
+            best = Some(kernel);
+        }
+    }
+    best.and_then(|kernel| kernel.name.strip_prefix("KERN-"))
+        .and_then(|slot| gpt.find(&format!("ROOT-{}", slot)))
+        .or_else(|| gpt.find("ROOT-A"))
+}
+
+/// Release information of the ChromeOS installed on a VM disk.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InstalledRelease {
+    /// Partition the release was read from, e.g. `ROOT-B`.
+    pub root: String,
+    pub release: LsbRelease,
+}
+
+/// Reads the release of the active root partition of the disk at `path`, or
+/// `None` if the disk has no ChromeOS installed.
+pub fn installed_release(path: &Path) -> Result<Option<InstalledRelease>, Error> {
+    let mut file = File::open(path)?;
+    let Ok(gpt) = gpt::read(&mut file) else {
+        return Ok(None);
+    };
+    let Some(root) = active_root(&gpt) else {
+        return Ok(None);
+    };
+    let release = read_lsb_release(&mut file, root).map_err(|err| {
+        invalid(
+            path,
+            format!("cannot read /etc/lsb-release from {}: {}", root.name, err),
+        )
+    })?;
+    Ok(Some(InstalledRelease {
+        root: root.name.clone(),
+        release,
+    }))
+}
+
+/// Whether the disk at `path` has a ChromeOS partition layout. A disk without
+/// a partition table (e.g. freshly created) counts as not installed.
+pub fn is_installed(path: &Path) -> Result<bool, Error> {
+    let mut file = File::open(path)?;
+    let Ok(gpt) = gpt::read(&mut file) else {
+        return Ok(false);
+    };
+    Ok(REQUIRED_PARTITIONS
+        .iter()
+        .all(|name| gpt.find(name).is_some()))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::fs;
+
+    const LSB_RELEASE: &str = "CHROMEOS_RELEASE_NAME=ChromeOS Flex\nCHROMEOS_RELEASE_BOARD=reven-signed-mp-v2keys\nCHROMEOS_RELEASE_VERSION=15917.71.0\nCHROMEOS_RELEASE_TRACK=stable-channel\nCHROMEOS_RELEASE_CHROME_MILESTONE=121\n";
+
+    /// A miniature recovery image: STATE, KERN-A and an ext2 ROOT-A.
+    pub(crate) fn recovery_image(lsb_release: &str) -> Vec<u8> {
+        let root = extfs::tests::ext2_with(&[("lsb-release", lsb_release.as_bytes())]);
+        let root_sectors = root.len() as u64 / gpt::SECTOR_SIZE;
+        let root_first = 64;
+        let mut disk = gpt::tests::disk_with(
+            root_first + root_sectors + 34,
+            &[
+                ("STATE", [1; 16], 40, 47, 0),
+                ("KERN-A", gpt::CHROMEOS_KERNEL_TYPE, 48, 63, 0),
+                (
+                    "ROOT-A",
+                    gpt::CHROMEOS_ROOTFS_TYPE,
+                    root_first,
+                    root_first + root_sectors - 1,
+                    0,
+                ),
+            ],
+        );
+        let at = (root_first * gpt::SECTOR_SIZE) as usize;
+        disk[at..at + root.len()].copy_from_slice(&root);
+        disk
+    }
+
+    #[test]
+    fn test_parse_lsb_release() {
+        let release = LsbRelease::parse(LSB_RELEASE);
+        assert_eq!(release.short_board(), "reven");
+        assert_eq!(release.short_channel(), "stable");
+        assert_eq!(release.version, "15917.71.0");
+        assert_eq!(release.milestone, "121");
+    }
+
+    #[test]
+    fn test_inspect_image() {
+        let path = std::env::temp_dir().join(format!(
+            "chromeos-launcher-image-{}.bin",
+            std::process::id()
+        ));
+        let image = recovery_image(LSB_RELEASE);
+        fs::write(&path, &image).unwrap();
+        let info = inspect_image(&path).unwrap();
+        assert_eq!(info.release.short_board(), "reven");
+        assert_eq!(info.gpt.partitions.len(), 3);
+
+        // A partial download loses the end of ROOT-A.
+        fs::write(&path, &image[..image.len() - 64 * 512]).unwrap();
+        assert!(inspect_image(&path).is_err());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_active_root() {
+        // KERN-B (priority 2, successful) wins over KERN-A (priority 1).
+        let layout = |kern_b_attributes| {
+            gpt::tests::disk_with(
+                100,
+                &[
+                    (
+                        "KERN-A",
+                        gpt::CHROMEOS_KERNEL_TYPE,
+                        40,
+                        49,
+                        0x0101_0000_0000_0000,
+                    ),
+                    ("ROOT-A", gpt::CHROMEOS_ROOTFS_TYPE, 50, 59, 0),
+                    (
+                        "KERN-B",
+                        gpt::CHROMEOS_KERNEL_TYPE,
+                        60,
+                        69,
+                        kern_b_attributes,
+                    ),
+                    ("ROOT-B", gpt::CHROMEOS_ROOTFS_TYPE, 70, 79, 0),
+                ],
+            )
+        };
+        let read = |disk: Vec<u8>| gpt::read(&mut std::io::Cursor::new(disk)).unwrap();
+
+        let gpt = read(layout(0x0102_0000_0000_0000));
+        assert_eq!(active_root(&gpt).unwrap().name, "ROOT-B");
+        // An update that never booted and ran out of tries is skipped.
+        let gpt = read(layout(0x0002_0000_0000_0000));
+        assert_eq!(active_root(&gpt).unwrap().name, "ROOT-A");
+        // Same priority: the earlier partition wins.
+        let gpt = read(layout(0x0101_0000_0000_0000));
+        assert_eq!(active_root(&gpt).unwrap().name, "ROOT-A");
+    }
+
+    #[test]
+    fn test_is_installed() {
+        let path =
+            std::env::temp_dir().join(format!("chromeos-launcher-disk-{}.img", std::process::id()));
+        fs::write(&path, recovery_image(LSB_RELEASE)).unwrap();
+        assert!(is_installed(&path).unwrap());
+        fs::write(&path, vec![0u8; 64 * 1024]).unwrap();
+        assert!(!is_installed(&path).unwrap());
+        fs::remove_file(path).unwrap();
+    }
+}
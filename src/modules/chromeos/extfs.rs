@@ -0,0 +1,450 @@
+use std::io::{Read, Seek, SeekFrom};
+
+const EXT_MAGIC: u16 = 0xEF53;
+const ROOT_INODE: u32 = 2;
+const INCOMPAT_64BIT: u32 = 0x80;
+const EXTENTS_FLAG: u32 = 0x80000;
+const INLINE_DATA_FLAG: u32 = 0x1000_0000;
+const EXTENT_MAGIC: u16 = 0xF30A;
+const MODE_TYPE_MASK: u16 = 0xF000;
+const MODE_DIR: u16 = 0x4000;
+const MODE_FILE: u16 = 0x8000;
+const MODE_SYMLINK: u16 = 0xA000;
+/// Files we read are small text files; refuse anything that could make us
+/// allocate absurd amounts of memory on a corrupted image.
+const MAX_FILE_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Read-only access to an ext2/3/4 filesystem, enough to pull single files
+/// such as `/etc/lsb-release` out of a ChromeOS root partition.
+pub struct ExtFs<'a, R> {
+    reader: &'a mut R,
+    offset: u64,
+    block_size: u64,
+    inodes_per_group: u32,
+    inode_size: u64,
+    desc_size: u64,
+    first_data_block: u64,
+    is_64bit: bool,
+}
+
+struct Inode {
+    mode: u16,
+    size: u64,
+    flags: u32,
+    block: [u8; 60],
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+impl<'a, R: Read + Seek> ExtFs<'a, R> {
+    /// Opens the filesystem starting `offset` bytes into `reader`.
+    pub fn open(reader: &'a mut R, offset: u64) -> Result<Self, String> {
+        let mut sb = [0u8; 1024];
+        reader
+            .seek(SeekFrom::Start(offset + 1024))
+            .and_then(|_| reader.read_exact(&mut sb))
+            .map_err(|_| "Filesystem superblock is truncated".to_string())?;
+        if u16_at(&sb, 56) != EXT_MAGIC {
+            return Err("Not an ext2/ext4 filesystem".to_string());
+        }
+        let log_block_size = u32_at(&sb, 24);
+        if log_block_size > 6 {
+            return Err("Unsupported filesystem block size".to_string());
+        }
+        let rev_level = u32_at(&sb, 76);
+        let incompat = u32_at(&sb, 96);
+        let is_64bit = incompat & INCOMPAT_64BIT != 0;
+        let inode_size = if rev_level == 0 {
+            128
+        } else {
+            u16_at(&sb, 88) as u64
+        };
+        let desc_size = if is_64bit {
+            u16_at(&sb, 254).max(32) as u64
+        } else {
+            32
+        };
+        let inodes_per_group = u32_at(&sb, 40);
+        if inodes_per_group == 0 || inode_size < 128 {
+            return Err("Corrupted filesystem superblock".to_string());
+        }
+
+        Ok(Self {
+            reader,
+            offset,
+            block_size: 1024 << log_block_size,
+            inodes_per_group,
+            inode_size,
+            desc_size,
+            first_data_block: u32_at(&sb, 20) as u64,
+            is_64bit,
+        })
+    }
+
+    fn read_at(&mut self, position: u64, buf: &mut [u8]) -> Result<(), String> {
+        self.reader
+            .seek(SeekFrom::Start(self.offset + position))
+            .and_then(|_| self.reader.read_exact(buf))
+            .map_err(|_| "Filesystem data is truncated".to_string())
+    }
+
+    fn read_block(&mut self, block: u64) -> Result<Vec<u8>, String> {
+        let mut buf = vec![0u8; self.block_size as usize];
+        if block != 0 {
+            self.read_at(block * self.block_size, &mut buf)?;
+        }
+        Ok(buf)
+    }
+
+    fn read_inode(&mut self, number: u32) -> Result<Inode, String> {
+        if number == 0 {
+            return Err("Invalid inode number".to_string());
+        }
+        let group = ((number - 1) / self.inodes_per_group) as u64;
+        let index = ((number - 1) % self.inodes_per_group) as u64;
+
+        let mut desc = vec![0u8; self.desc_size as usize];
+        let table_start = (self.first_data_block + 1) * self.block_size;
+        self.read_at(table_start + group * self.desc_size, &mut desc)?;
+        let mut inode_table = u32_at(&desc, 8) as u64;
+        if self.is_64bit && self.desc_size >= 64 {
+            inode_table |= (u32_at(&desc, 0x28) as u64) << 32;
+        }
+
+        let mut raw = [0u8; 128];
+        self.read_at(
+            inode_table * self.block_size + index * self.inode_size,
+            &mut raw,
+        )?;
+        Ok(Inode {
+            mode: u16_at(&raw, 0),
+            size: u32_at(&raw, 4) as u64 | ((u32_at(&raw, 108) as u64) << 32),
+            flags: u32_at(&raw, 32),
+            block: raw[40..100].try_into().unwrap(),
+        })
+    }
+
+    /// Physical block numbers backing the first `count` logical blocks of an
+    /// inode; holes are reported as block 0.
+    fn data_blocks(&mut self, inode: &Inode, count: u64) -> Result<Vec<u64>, String> {
+        let mut blocks = vec![0u64; count as usize];
+        if inode.flags & EXTENTS_FLAG != 0 {
+            self.walk_extents(&inode.block, &mut blocks, 0)?;
+        } else {
+            let pointers: Vec<u64> = (0..15)
+                .map(|i| u32_at(&inode.block, i * 4) as u64)
+                .collect();
+            let per_block = self.block_size / 4;
+            let mut logical = 0u64;
+            for &direct in &pointers[..12] {
+                if logical >= count {
+                    break;
+                }
+                blocks[logical as usize] = direct;
+                logical += 1;
+            }
+            for (depth, &pointer) in pointers[12..].iter().enumerate() {
+                if logical >= count {
+                    break;
+                }
+                let span = per_block.pow(depth as u32 + 1);
+                self.walk_indirect(pointer, depth as u32, logical, &mut blocks)?;
+                logical += span;
+            }
+        }
+        Ok(blocks)
+    }
+
+    fn walk_indirect(
+        &mut self,
+        pointer: u64,
+        depth: u32,
+        first_logical: u64,
+        blocks: &mut [u64],
+    ) -> Result<(), String> {
+        if pointer == 0 || first_logical >= blocks.len() as u64 {
+            return Ok(());
+        }
+        let data = self.read_block(pointer)?;
+        let per_block = self.block_size / 4;
+        let span = per_block.pow(depth);
+        for i in 0..per_block {
+            let logical = first_logical + i * span;
+            if logical >= blocks.len() as u64 {
+                break;
+            }
+            let child = u32_at(&data, (i * 4) as usize) as u64;
+            if depth == 0 {
+                blocks[logical as usize] = child;
+            } else {
+                self.walk_indirect(child, depth - 1, logical, blocks)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn walk_extents(&mut self, node: &[u8], blocks: &mut [u64], level: u32) -> Result<(), String> {
+        if level > 5 || u16_at(node, 0) != EXTENT_MAGIC {
+            return Err("Corrupted extent tree".to_string());
+        }
+        let entries = u16_at(node, 2) as usize;
+        let depth = u16_at(node, 6);
+        for i in 0..entries {
+            let entry = 12 + i * 12;
+            if entry + 12 > node.len() {
+                return Err("Corrupted extent tree".to_string());
+            }
+            if depth == 0 {
+                let first = u32_at(node, entry) as u64;
+                let mut len = u16_at(node, entry + 4) as u64;
+                if len > 32768 {
+                    // Uninitialized extent: reads as zeros.
+                    continue;
+                }
+                let start =
+                    ((u16_at(node, entry + 6) as u64) << 32) | u32_at(node, entry + 8) as u64;
+                len = len.min((blocks.len() as u64).saturating_sub(first));
+                for j in 0..len {
+                    blocks[(first + j) as usize] = start + j;
+                }
+            } else {
+                let leaf =
+                    ((u16_at(node, entry + 8) as u64) << 32) | u32_at(node, entry + 4) as u64;
+                let child = self.read_block(leaf)?;
+                self.walk_extents(&child, blocks, level + 1)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_inode_data(&mut self, inode: &Inode) -> Result<Vec<u8>, String> {
+        if inode.size > MAX_FILE_SIZE {
+            return Err("File is too large".to_string());
+        }
+        if inode.flags & INLINE_DATA_FLAG != 0 {
+            return Err("Inline file data is not supported".to_string());
+        }
+        let count = inode.size.div_ceil(self.block_size);
+        let mut data = Vec::with_capacity((count * self.block_size) as usize);
+        for block in self.data_blocks(inode, count)? {
+            data.extend(self.read_block(block)?);
+        }
+        data.truncate(inode.size as usize);
+        Ok(data)
+    }
+
+    fn lookup_in(&mut self, dir: &Inode, name: &str) -> Result<Option<u32>, String> {
+        let data = self.read_inode_data(dir)?;
+        let block_size = self.block_size as usize;
+        for block in data.chunks(block_size) {
+            let mut pos = 0;
+            while pos + 8 <= block.len() {
+                let inode = u32_at(block, pos);
+                let rec_len = u16_at(block, pos + 4) as usize;
+                let name_len = block[pos + 6] as usize;
+                if rec_len < 8 || pos + rec_len > block.len() {
+                    break;
+                }
+                if inode != 0
+                    && pos + 8 + name_len <= block.len()
+                    && &block[pos + 8..pos + 8 + name_len] == name.as_bytes()
+                {
+                    return Ok(Some(inode));
+                }
+                pos += rec_len;
+            }
+        }
+        Ok(None)
+    }
+
+    fn resolve(&mut self, path: &str, links_left: u32) -> Result<Inode, String> {
+        let mut inode = self.read_inode(ROOT_INODE)?;
+        let mut current = String::new();
+        let components: Vec<&str> = path.split('/').filter(|c| !c.is_empty()).collect();
+        for (i, component) in components.iter().enumerate() {
+            if inode.mode & MODE_TYPE_MASK != MODE_DIR {
+                return Err(format!("{} is not a directory", current));
+            }
+            let number = self
+                .lookup_in(&inode, component)?
+                .ok_or_else(|| format!("{}/{} not found", current, component))?;
+            let next = self.read_inode(number)?;
+            if next.mode & MODE_TYPE_MASK == MODE_SYMLINK {
+                if links_left == 0 {
+                    return Err("Too many levels of symbolic links".to_string());
+                }
+                let target = if next.size < 60 && next.flags & EXTENTS_FLAG == 0 {
+                    next.block[..next.size as usize].to_vec()
+                } else {
+                    self.read_inode_data(&next)?
+                };
+                let target = String::from_utf8_lossy(&target).to_string();
+                let rest = components[i + 1..].join("/");
+                let joined = if target.starts_with('/') {
+                    format!("{}/{}", target, rest)
+                } else {
+                    format!("{}/{}/{}", current, target, rest)
+                };
+                return self.resolve(&normalize(&joined), links_left - 1);
+            }
+            current = format!("{}/{}", current, component);
+            inode = next;
+        }
+        Ok(inode)
+    }
+
+    /// Reads the regular file at the absolute `path`, following symlinks.
+    pub fn read_file(&mut self, path: &str) -> Result<Vec<u8>, String> {
+        let inode = self.resolve(path, 8)?;
+        if inode.mode & MODE_TYPE_MASK != MODE_FILE {
+            return Err(format!("{} is not a regular file", path));
+        }
+        self.read_inode_data(&inode)
+    }
+}
+
+/// Collapses `.` and `..` components of an absolute path.
+fn normalize(path: &str) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            other => parts.push(other),
+        }
+    }
+    format!("/{}", parts.join("/"))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const BLOCK: usize = 1024;
+
+    fn put_u16(buf: &mut [u8], offset: usize, value: u16) {
+        buf[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn put_u32(buf: &mut [u8], offset: usize, value: u32) {
+        buf[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    /// Builds a tiny single-group ext2 image (1 KiB blocks) holding `files`,
+    /// each placed under `/etc`. Every file uses direct blocks, except that
+    /// files longer than 12 blocks go through a single indirect block.
+    pub(crate) fn ext2_with(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut image = vec![0u8; 256 * BLOCK];
+        let sb = 1024;
+        put_u32(&mut image, sb, 32);
+        put_u32(&mut image, sb + 4, 256);
+        put_u32(&mut image, sb + 20, 1);
+        put_u32(&mut image, sb + 32, 8192);
+        put_u32(&mut image, sb + 40, 32);
+        put_u16(&mut image, sb + 56, EXT_MAGIC);
+        put_u32(&mut image, sb + 76, 1);
+        put_u16(&mut image, sb + 88, 128);
+        // Group descriptor in block 2, inode table in blocks 5..9.
+        put_u32(&mut image, 2 * BLOCK + 8, 5);
+
+        let mut next_block = 10u32;
+        let mut write_inode = |image: &mut Vec<u8>, number: u32, mode: u16, data: &[u8]| {
+            let at = 5 * BLOCK + (number as usize - 1) * 128;
+            put_u16(image, at, mode);
+            put_u32(image, at + 4, data.len() as u32);
+            let blocks: Vec<u32> = data
+                .chunks(BLOCK)
+                .map(|chunk| {
+                    let block = next_block;
+                    next_block += 1;
+                    image[block as usize * BLOCK..block as usize * BLOCK + chunk.len()]
+                        .copy_from_slice(chunk);
+                    block
+                })
+                .collect();
+            for (i, block) in blocks.iter().take(12).enumerate() {
+                put_u32(image, at + 40 + i * 4, *block);
+            }
+            if blocks.len() > 12 {
+                let indirect = next_block;
+                next_block += 1;
+                for (i, block) in blocks[12..].iter().enumerate() {
+                    put_u32(image, indirect as usize * BLOCK + i * 4, *block);
+                }
+                put_u32(image, at + 40 + 48, indirect);
+            }
+        };
+
+        let dir = |entries: &[(u32, &str)]| {
+            let mut block = vec![0u8; BLOCK];
+            let mut pos = 0;
+            for (i, (inode, name)) in entries.iter().enumerate() {
+                let rec_len = if i + 1 == entries.len() {
+                    BLOCK - pos
+                } else {
+                    (8 + name.len()).div_ceil(4) * 4
+                };
+                put_u32(&mut block, pos, *inode);
+                put_u16(&mut block, pos + 4, rec_len as u16);
+                block[pos + 6] = name.len() as u8;
+                block[pos + 8..pos + 8 + name.len()].copy_from_slice(name.as_bytes());
+                pos += rec_len;
+            }
+            block
+        };
+
+        write_inode(
+            &mut image,
+            2,
+            MODE_DIR | 0o755,
+            &dir(&[(2, "."), (2, ".."), (11, "etc")]),
+        );
+        let mut etc_entries = vec![(11, "."), (2, "..")];
+        for (i, (name, _)) in files.iter().enumerate() {
+            etc_entries.push((12 + i as u32, name));
+        }
+        write_inode(&mut image, 11, MODE_DIR | 0o755, &dir(&etc_entries));
+        for (i, (_, data)) in files.iter().enumerate() {
+            write_inode(&mut image, 12 + i as u32, MODE_FILE | 0o644, data);
+        }
+        image
+    }
+
+    #[test]
+    fn test_read_file() {
+        let big: Vec<u8> = (0..20 * BLOCK).map(|i| (i % 251) as u8).collect();
+        let image = ext2_with(&[
+            ("lsb-release", b"CHROMEOS_RELEASE_BOARD=reven\n"),
+            ("big", &big),
+        ]);
+        let mut cursor = Cursor::new(image);
+        let mut fs = ExtFs::open(&mut cursor, 0).unwrap();
+        assert_eq!(
+            fs.read_file("/etc/lsb-release").unwrap(),
+            b"CHROMEOS_RELEASE_BOARD=reven\n"
+        );
+        assert_eq!(fs.read_file("/etc/big").unwrap(), big);
+        assert!(fs.read_file("/etc/missing").is_err());
+        assert!(fs.read_file("/etc").is_err());
+    }
+
+    #[test]
+    fn test_rejects_non_ext() {
+        let mut cursor = Cursor::new(vec![0u8; 4096]);
+        assert!(ExtFs::open(&mut cursor, 0).is_err());
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("/etc/../usr/./lib/"), "/usr/lib");
+    }
+}
@@ -0,0 +1,438 @@
+use std::io::{Read, Seek, SeekFrom, Write};
+
+pub const SECTOR_SIZE: u64 = 512;
+
+/// ChromeOS kernel partition type, `FE3A2A5D-4F32-41A7-B725-ACCC3285A309`.
+pub const CHROMEOS_KERNEL_TYPE: [u8; 16] = [
+    0x5D, 0x2A, 0x3A, 0xFE, 0x32, 0x4F, 0xA7, 0x41, 0xB7, 0x25, 0xAC, 0xCC, 0x32, 0x85, 0xA3, 0x09,
+];
+/// ChromeOS root filesystem partition type, `3CB8E202-3B7E-47DD-8A3C-7FF2A13CFCEC`.
+pub const CHROMEOS_ROOTFS_TYPE: [u8; 16] = [
+    0x02, 0xE2, 0xB8, 0x3C, 0x7E, 0x3B, 0xDD, 0x47, 0x8A, 0x3C, 0x7F, 0xF2, 0xA1, 0x3C, 0xFC, 0xEC,
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Partition {
+    /// 1-based partition number.
+    pub number: u32,
+    pub type_guid: [u8; 16],
+    pub unique_guid: [u8; 16],
+    pub name: String,
+    pub first_lba: u64,
+    pub last_lba: u64,
+    pub attributes: u64,
+}
+
+// `read` only returns partitions whose byte range fits in a u64, so the
+// arithmetic below cannot overflow.
+impl Partition {
+    pub fn offset(&self) -> u64 {
+        self.first_lba * SECTOR_SIZE
+    }
+
+    /// Byte offset just past the end of the partition.
+    pub fn end(&self) -> u64 {
+        (self.last_lba + 1) * SECTOR_SIZE
+    }
+
+    pub fn size(&self) -> u64 {
+        self.end() - self.offset()
+    }
+
+    /// The unique partition GUID in the form the kernel's `root=PARTUUID=`
+    /// expects.
+    pub fn partuuid(&self) -> String {
+        let g = &self.unique_guid;
+        format!(
+            "{:08x}-{:04x}-{:04x}-{:02x}{:02x}-{}",
+            u32::from_le_bytes(g[0..4].try_into().unwrap()),
+            u16::from_le_bytes([g[4], g[5]]),
+            u16::from_le_bytes([g[6], g[7]]),
+            g[8],
+            g[9],
+            g[10..]
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<String>()
+        )
+    }
+
+    /// ChromeOS kernel boot priority (attribute bits 48-51).
+    pub fn priority(&self) -> u8 {
+        ((self.attributes >> 48) & 0xF) as u8
+    }
+
+    /// Remaining boot attempts (attribute bits 52-55).
+    pub fn tries(&self) -> u8 {
+        ((self.attributes >> 52) & 0xF) as u8
+    }
+
+    /// Whether the kernel has booted successfully (attribute bit 56).
+    pub fn successful(&self) -> bool {
+        (self.attributes >> 56) & 1 == 1
+    }
+}
+
+#[derive(Debug)]
+pub struct Gpt {
+    /// LBA of the backup header, i.e. the last sector of the disk the table
+    /// was written for.
+    pub alternate_lba: u64,
+    pub partitions: Vec<Partition>,
+}
+
+impl Gpt {
+    pub fn find(&self, name: &str) -> Option<&Partition> {
+        self.partitions.iter().find(|p| p.name == name)
+    }
+
+    /// Size in bytes the disk must have to hold the whole table.
+    pub fn required_size(&self) -> u64 {
+        (self.alternate_lba + 1) * SECTOR_SIZE
+    }
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+/// Where a GPT header says its partition entries are.
+struct EntryLayout {
+    /// Byte offset of the entries.
+    offset: u64,
+    count: usize,
+    entry_size: usize,
+}
+
+impl EntryLayout {
+    fn of(header: &[u8]) -> Result<Self, String> {
+        let invalid = || "Invalid GPT partition entry layout".to_string();
+        let count = u32_at(header, 80) as usize;
+        let entry_size = u32_at(header, 84) as usize;
+        // The UEFI spec requires 128 * 2^n byte entries; real tables use 128.
+        if !(128..=4096).contains(&entry_size) || !entry_size.is_power_of_two() || count > 1024 {
+            return Err(invalid());
+        }
+        let offset = u64_at(header, 72)
+            .checked_mul(SECTOR_SIZE)
+            .ok_or_else(invalid)?;
+        Ok(Self {
+            offset,
+            count,
+            entry_size,
+        })
+    }
+
+    fn len(&self) -> usize {
+        self.count * self.entry_size
+    }
+}
+
+/// Reads the primary GPT of a disk image.
+pub fn read<R: Read + Seek>(reader: &mut R) -> Result<Gpt, String> {
+    let mut header = [0u8; SECTOR_SIZE as usize];
+    reader
+        .seek(SeekFrom::Start(SECTOR_SIZE))
+        .and_then(|_| reader.read_exact(&mut header))
+        .map_err(|_| "Image is too small to contain a GPT".to_string())?;
+    if &header[0..8] != b"EFI PART" {
+        return Err("No GPT found (not a ChromeOS disk image)".to_string());
+    }
+    let header_size = u32_at(&header, 12) as usize;
+    if !(92..=header.len()).contains(&header_size) {
+        return Err("Invalid GPT header size".to_string());
+    }
+    let mut check = header[..header_size].to_vec();
+    check[16..20].fill(0);
+    if crc32(&check) != u32_at(&header, 16) {
+        return Err("GPT header checksum mismatch (corrupted image?)".to_string());
+    }
+
+    let alternate_lba = u64_at(&header, 32);
+    let layout = EntryLayout::of(&header)?;
+    let mut entries = vec![0u8; layout.len()];
+    reader
+        .seek(SeekFrom::Start(layout.offset))
+        .and_then(|_| reader.read_exact(&mut entries))
+        .map_err(|_| "GPT partition entries are truncated".to_string())?;
+    if crc32(&entries) != u32_at(&header, 88) {
+        return Err("GPT partition entries checksum mismatch (corrupted image?)".to_string());
+    }
+
+    let partitions = entries
+        .chunks_exact(layout.entry_size)
+        .enumerate()
+        .filter(|(_, entry)| entry[0..16].iter().any(|&b| b != 0))
+        .map(|(index, entry)| {
+            let (first_lba, last_lba) = (u64_at(entry, 32), u64_at(entry, 40));
+            if first_lba > last_lba
+                || last_lba
+                    .checked_add(1)
+                    .and_then(|end| end.checked_mul(SECTOR_SIZE))
+                    .is_none()
+            {
+                return Err(format!("Invalid GPT partition {}", index + 1));
+            }
+            let units: Vec<u16> = entry[56..128]
+                .chunks_exact(2)
+                .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                .take_while(|&u| u != 0)
+                .collect();
+            Ok(Partition {
+                number: index as u32 + 1,
+                type_guid: entry[0..16].try_into().unwrap(),
+                unique_guid: entry[16..32].try_into().unwrap(),
+                name: String::from_utf16_lossy(&units),
+                first_lba,
+                last_lba,
+                attributes: u64_at(entry, 48),
+            })
+        })
+        .collect::<Result<_, _>>()?;
+
+    Ok(Gpt {
+        alternate_lba,
+        partitions,
+    })
+}
+
+/// Moves the backup GPT to the end of a disk that was grown to `disk_size`
+/// bytes and lets the primary header describe the larger disk, as
+/// `sgdisk -e` does. The space gained is left unpartitioned.
+pub fn relocate_backup<F: Read + Write + Seek>(disk: &mut F, disk_size: u64) -> Result<(), String> {
+    let io_error = |err: std::io::Error| err.to_string();
+    let mut header = [0u8; SECTOR_SIZE as usize];
+    disk.seek(SeekFrom::Start(SECTOR_SIZE)).map_err(io_error)?;
+    disk.read_exact(&mut header).map_err(io_error)?;
+    if &header[0..8] != b"EFI PART" {
+        return Err("No GPT found".to_string());
+    }
+    let header_size = u32_at(&header, 12) as usize;
+    if !(92..=header.len()).contains(&header_size) {
+        return Err("Invalid GPT header size".to_string());
+    }
+    let old_backup_lba = u64_at(&header, 32);
+    let layout = EntryLayout::of(&header)?;
+    let entries_sectors = (layout.len() as u64).div_ceil(SECTOR_SIZE);
+
+    let Some(last_lba) = (disk_size / SECTOR_SIZE).checked_sub(1) else {
+        return Err("The disk is too small for a GPT".to_string());
+    };
+    if last_lba <= old_backup_lba {
+        return Ok(());
+    }
+    // The backup entries and the sector before them must lie past the primary
+    // table.
+    let backup_entries_lba = match last_lba.checked_sub(entries_sectors) {
+        Some(lba) if lba > 2 + entries_sectors => lba,
+        _ => return Err("The disk is too small for its GPT".to_string()),
+    };
+    let mut entries = vec![0u8; layout.len()];
+    disk.seek(SeekFrom::Start(layout.offset))
+        .map_err(io_error)?;
+    disk.read_exact(&mut entries).map_err(io_error)?;
+
+    let seal = |header: &mut [u8; SECTOR_SIZE as usize]| {
+        header[16..20].fill(0);
+        let crc = crc32(&header[..header_size]);
+        header[16..20].copy_from_slice(&crc.to_le_bytes());
+    };
+    header[32..40].copy_from_slice(&last_lba.to_le_bytes());
+    header[48..56].copy_from_slice(&(backup_entries_lba - 1).to_le_bytes());
+    seal(&mut header);
+    let mut backup = header;
+    backup[24..32].copy_from_slice(&last_lba.to_le_bytes());
+    backup[32..40].copy_from_slice(&1u64.to_le_bytes());
+    backup[72..80].copy_from_slice(&backup_entries_lba.to_le_bytes());
+    seal(&mut backup);
+
+    let mut write_at = |lba: u64, data: &[u8]| {
+        disk.seek(SeekFrom::Start(lba * SECTOR_SIZE))
+            .and_then(|_| disk.write_all(data))
+            .map_err(io_error)
+    };
+    write_at(backup_entries_lba, &entries)?;
+    write_at(last_lba, &backup)?;
+    write_at(1, &header)?;
+    // Drop the stale backup header so tools do not mistake it for the real one.
+    write_at(old_backup_lba, &[0u8; SECTOR_SIZE as usize])?;
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Builds a disk image with a primary GPT describing `partitions`
+    /// (name, type, first LBA, last LBA, attributes).
+    pub(crate) fn disk_with(
+        sectors: u64,
+        partitions: &[(&str, [u8; 16], u64, u64, u64)],
+    ) -> Vec<u8> {
+        let mut disk = vec![0u8; (sectors * SECTOR_SIZE) as usize];
+        let mut entries = vec![0u8; 128 * 128];
+        for (index, (name, type_guid, first, last, attributes)) in partitions.iter().enumerate() {
+            let entry = &mut entries[index * 128..(index + 1) * 128];
+            entry[0..16].copy_from_slice(type_guid);
+            entry[16] = index as u8 + 1;
+            entry[32..40].copy_from_slice(&first.to_le_bytes());
+            entry[40..48].copy_from_slice(&last.to_le_bytes());
+            entry[48..56].copy_from_slice(&attributes.to_le_bytes());
+            for (i, unit) in name.encode_utf16().enumerate() {
+                entry[56 + i * 2..58 + i * 2].copy_from_slice(&unit.to_le_bytes());
+            }
+        }
+        let header = &mut disk[512..1024];
+        header[0..8].copy_from_slice(b"EFI PART");
+        header[8..12].copy_from_slice(&0x10000u32.to_le_bytes());
+        header[12..16].copy_from_slice(&92u32.to_le_bytes());
+        header[24..32].copy_from_slice(&1u64.to_le_bytes());
+        header[32..40].copy_from_slice(&(sectors - 1).to_le_bytes());
+        header[72..80].copy_from_slice(&2u64.to_le_bytes());
+        header[80..84].copy_from_slice(&128u32.to_le_bytes());
+        header[84..88].copy_from_slice(&128u32.to_le_bytes());
+        header[88..92].copy_from_slice(&crc32(&entries).to_le_bytes());
+        let crc = crc32(&header[..92]);
+        header[16..20].copy_from_slice(&crc.to_le_bytes());
+        disk[1024..1024 + entries.len()].copy_from_slice(&entries);
+        disk
+    }
+
+    #[test]
+    fn test_relocate_backup() {
+        let mut disk = disk_with(100, &[("STATE", [1; 16], 40, 59, 0)]);
+        disk.resize(300 * 512, 0);
+        let mut cursor = Cursor::new(disk);
+        relocate_backup(&mut cursor, 300 * 512).unwrap();
+        let disk = cursor.into_inner();
+
+        let gpt = read(&mut Cursor::new(&disk)).unwrap();
+        assert_eq!(gpt.required_size(), 300 * 512);
+        assert_eq!(gpt.find("STATE").unwrap().last_lba, 59);
+        let backup = &disk[299 * 512..300 * 512];
+        assert_eq!(&backup[0..8], b"EFI PART");
+        assert_eq!(u64_at(backup, 24), 299);
+        assert_eq!(u64_at(backup, 72), 299 - 32);
+        let mut check = backup[..92].to_vec();
+        check[16..20].fill(0);
+        assert_eq!(crc32(&check), u32_at(backup, 16));
+        assert_eq!(
+            &disk[1024..1024 + 128],
+            &disk[(299 - 32) * 512..(299 - 32) * 512 + 128]
+        );
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_read_partitions() {
+        let disk = disk_with(
+            100,
+            &[
+                ("STATE", [1; 16], 40, 59, 0),
+                (
+                    "KERN-A",
+                    CHROMEOS_KERNEL_TYPE,
+                    60,
+                    69,
+                    0x0115_0000_0000_0000,
+                ),
+            ],
+        );
+        let gpt = read(&mut Cursor::new(disk)).unwrap();
+        assert_eq!(gpt.required_size(), 100 * 512);
+        let kernel = gpt.find("KERN-A").unwrap();
+        assert_eq!(kernel.number, 2);
+        assert_eq!(kernel.offset(), 60 * 512);
+        assert_eq!(kernel.size(), 10 * 512);
+        assert_eq!(kernel.partuuid(), "00000002-0000-0000-0000-000000000000");
+        assert_eq!(
+            (kernel.priority(), kernel.tries(), kernel.successful()),
+            (5, 1, true)
+        );
+    }
+
+    #[test]
+    fn test_rejects_corruption() {
+        let mut disk = disk_with(100, &[("STATE", [1; 16], 40, 59, 0)]);
+        disk[1024 + 60] ^= 0xFF;
+        assert!(read(&mut Cursor::new(disk)).is_err());
+        assert!(read(&mut Cursor::new(vec![0u8; 4096])).is_err());
+    }
+
+    /// Rewrites a header field and fixes up the header checksum, so only the
+    /// field itself is wrong.
+    fn with_header_field(mut disk: Vec<u8>, offset: usize, value: u32) -> Vec<u8> {
+        let header = &mut disk[512..1024];
+        header[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        header[16..20].fill(0);
+        let crc = crc32(&header[..92]);
+        header[16..20].copy_from_slice(&crc.to_le_bytes());
+        disk
+    }
+
+    #[test]
+    fn test_rejects_malformed_partitions() {
+        for (first, last) in [(60, 59), (40, u64::MAX), (40, u64::MAX / 512)] {
+            let disk = disk_with(100, &[("STATE", [1; 16], first, last, 0)]);
+            assert_eq!(
+                read(&mut Cursor::new(disk)).unwrap_err(),
+                "Invalid GPT partition 1"
+            );
+        }
+    }
+
+    #[test]
+    fn test_relocate_backup_rejects_malformed_headers() {
+        let disk = disk_with(100, &[("STATE", [1; 16], 40, 59, 0)]);
+        let mut cursor = Cursor::new(with_header_field(disk.clone(), 84, 0xFFFF_FFFF));
+        assert!(relocate_backup(&mut cursor, 300 * 512).is_err());
+        let mut cursor = Cursor::new(with_header_field(disk.clone(), 72, 0xFFFF_FFFF));
+        cursor.get_mut()[512 + 76..512 + 80].fill(0xFF);
+        assert!(relocate_backup(&mut cursor, 300 * 512).is_err());
+        assert!(relocate_backup(&mut Cursor::new(disk), 100).is_err());
+    }
+
+    #[test]
+    fn test_rejects_malformed_entry_layout() {
+        let disk = disk_with(100, &[("STATE", [1; 16], 40, 59, 0)]);
+        for (offset, value) in [
+            (84, 0xFFFF_FFFF),
+            (84, 8192),
+            (84, 192),
+            (84, 64),
+            (80, 4096),
+        ] {
+            let error = read(&mut Cursor::new(with_header_field(
+                disk.clone(),
+                offset,
+                value,
+            )))
+            .unwrap_err();
+            assert_eq!(error, "Invalid GPT partition entry layout");
+        }
+    }
+}
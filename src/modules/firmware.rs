@@ -0,0 +1,625 @@
+use crate::utils::error::Error;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Environment variable holding extra directories (separated by `:`) to scan for
+/// firmware images before the distribution defaults.
+pub const FIRMWARE_PATH_ENV: &str = "CHROMEOS_LAUNCHER_FIRMWARE_PATH";
+
+/// How a machine boots: UEFI through OVMF/AAVMF, or SeaBIOS for images that
+/// predate UEFI support.
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum FirmwareType {
+    Uefi,
+    Bios,
+}
+
+impl FromStr for FirmwareType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "uefi" => Ok(FirmwareType::Uefi),
+            "bios" => Ok(FirmwareType::Bios),
+            _ => Err(format!(
+                "Unknown firmware type: {} (expected uefi or bios)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for FirmwareType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FirmwareType::Uefi => write!(f, "uefi"),
+            FirmwareType::Bios => write!(f, "bios"),
+        }
+    }
+}
+
+/// A UEFI firmware build: a read-only code image plus the template used to seed
+/// each machine's writable variable store.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Firmware {
+    pub description: String,
+    pub code: PathBuf,
+    pub vars_template: PathBuf,
+    pub format: String,
+    pub features: Vec<String>,
+}
+
+/// What the caller needs from the firmware.
+pub struct FirmwareQuery<'a> {
+    /// QEMU target architecture, e.g. `x86_64` or `aarch64`.
+    pub arch: &'a str,
+    /// Machine type family, e.g. `pc-q35` or `virt`.
+    pub machine: &'a str,
+    /// Features the firmware must advertise.
+    pub features: &'a [&'a str],
+    /// Features the firmware must not advertise.
+    pub excluded_features: &'a [&'a str],
+}
+
+impl FirmwareQuery<'_> {
+    fn accepts(&self, features: &[String]) -> bool {
+        self.features
+            .iter()
+            .all(|f| features.iter().any(|have| have == f))
+            && !features
+                .iter()
+                .any(|have| self.excluded_features.contains(&have.as_str()))
+    }
+}
+
+impl Firmware {
+    /// Builds a firmware entry from an explicit code/vars pair.
+    pub fn from_paths(code: PathBuf, vars_template: PathBuf, format: &str) -> Self {
+        Self {
+            description: code.display().to_string(),
+            features: infer_features(&code, &vars_template),
+            code,
+            vars_template,
+            format: format.to_string(),
+        }
+    }
+
+    pub fn has_feature(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct Descriptor {
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    interface_types: Vec<String>,
+    mapping: Mapping,
+    #[serde(default)]
+    targets: Vec<Target>,
+    #[serde(default)]
+    features: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct Mapping {
+    device: String,
+    mode: Option<String>,
+    executable: Option<FlashFile>,
+    nvram_template: Option<FlashFile>,
+}
+
+#[derive(Deserialize)]
+struct FlashFile {
+    filename: PathBuf,
+    #[serde(default = "default_format")]
+    format: String,
+}
+
+#[derive(Deserialize)]
+struct Target {
+    architecture: String,
+    #[serde(default)]
+    machines: Vec<String>,
+}
+
+fn default_format() -> String {
+    "raw".to_string()
+}
+
+impl Descriptor {
+    /// Converts the descriptor into a [`Firmware`] if it describes a split
+    /// code/vars flash build that satisfies `query`.
+    fn matching(self, query: &FirmwareQuery) -> Option<Firmware> {
+        if !self.interface_types.iter().any(|t| t == "uefi") {
+            return None;
+        }
+        if self.mapping.device != "flash"
+            || self.mapping.mode.as_deref().unwrap_or("split") != "split"
+        {
+            return None;
+        }
+        let targets_match = self.targets.iter().any(|target| {
+            target.architecture == query.arch
+                && target
+                    .machines
+                    .iter()
+                    .any(|pattern| machine_matches(pattern, query.machine))
+        });
+        if !targets_match {
+            return None;
+        }
+        if !query.accepts(&self.features) {
+            return None;
+        }
+
+        let executable = self.mapping.executable?;
+        let nvram_template = self.mapping.nvram_template?;
+        if executable.format != nvram_template.format {
+            return None;
+        }
+        Some(Firmware {
+            description: self.description,
+            code: executable.filename,
+            vars_template: nvram_template.filename,
+            format: executable.format,
+            features: self.features,
+        })
+    }
+}
+
+/// Checks a descriptor machine pattern such as `pc-q35-*` against a machine family.
+fn machine_matches(pattern: &str, machine: &str) -> bool {
+    let prefix = pattern.trim_end_matches('*');
+    prefix.is_empty() || prefix.starts_with(machine) || machine.starts_with(prefix)
+}
+
+/// Directories holding QEMU firmware descriptors, highest priority first.
+fn descriptor_dirs(arch: &str) -> Vec<PathBuf> {
+    let mut dirs_list = Vec::new();
+    if let Some(config) = dirs::config_dir() {
+        dirs_list.push(config.join("qemu/firmware"));
+    }
+    dirs_list.push(PathBuf::from("/etc/qemu/firmware"));
+    dirs_list.push(PathBuf::from("/usr/share/qemu/firmware"));
+    // Nix and other prefix installs ship descriptors next to the QEMU binary.
+    if let Some(prefix) = qemu_prefix(&format!("qemu-system-{}", arch)) {
+        dirs_list.push(prefix.join("share/qemu/firmware"));
+    }
+    dirs_list
+}
+
+fn qemu_prefix(binary_name: &str) -> Option<PathBuf> {
+    let path_env = env::var_os("PATH")?;
+    env::split_paths(&path_env).find_map(|dir| {
+        let binary = dir.join(binary_name);
+        if binary.is_file() {
+            let resolved = fs::canonicalize(binary).ok()?;
+            resolved.parent()?.parent().map(Path::to_path_buf)
+        } else {
+            None
+        }
+    })
+}
+
+/// Reads descriptors from `dirs_list` following QEMU's rules: a file name found in
+/// a higher-priority directory shadows the same name further down, and the
+/// resulting set is ordered by file name.
+fn load_descriptors(dirs_list: &[PathBuf]) -> Vec<(PathBuf, Descriptor)> {
+    let mut by_name: BTreeMap<String, PathBuf> = BTreeMap::new();
+    for dir in dirs_list {
+        let Ok(entries) = fs::read_dir(dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
+                by_name.entry(name.to_string()).or_insert(path);
+            }
+        }
+    }
+
+    by_name
+        .into_values()
+        .filter_map(|path| {
+            let content = fs::read_to_string(&path).ok()?;
+            let descriptor = serde_json::from_str(&content).ok()?;
+            Some((path, descriptor))
+        })
+        .collect()
+}
+
+fn find_in_descriptors(
+    dirs_list: &[PathBuf],
+    query: &FirmwareQuery,
+    keep: &impl Fn(&Firmware) -> bool,
+) -> Option<Firmware> {
+    load_descriptors(dirs_list)
+        .into_iter()
+        .filter_map(|(_, descriptor)| descriptor.matching(query))
+        .find(|firmware| {
+            firmware.code.exists() && firmware.vars_template.exists() && keep(firmware)
+        })
+}
+
+/// Directories scanned for `*CODE*.fd` / `*VARS*.fd` when no descriptor matches:
+/// the user's search path first, then the usual distribution locations.
+fn scan_dirs(arch: &str) -> Result<Vec<PathBuf>, Error> {
+    let mut dirs_list = Vec::new();
+    match env::var(FIRMWARE_PATH_ENV) {
+        Ok(value) => {
+            dirs_list.extend(env::split_paths(&value).filter(|p| !p.as_os_str().is_empty()))
+        }
+        Err(env::VarError::NotPresent) => {}
+        Err(err) => return Err(err.into()),
+    }
+    let defaults: &[&str] = match arch {
+        "x86_64" => &[
+            "/usr/share/OVMF",
+            "/usr/share/edk2/ovmf",
+            "/usr/share/edk2/x64",
+            "/usr/share/edk2-ovmf/x64",
+            "/usr/share/qemu",
+        ],
+        "aarch64" => &[
+            "/usr/share/AAVMF",
+            "/usr/share/edk2/aarch64",
+            "/usr/share/qemu-efi-aarch64",
+            "/usr/share/qemu",
+        ],
+        _ => &[],
+    };
+    dirs_list.extend(defaults.iter().map(PathBuf::from));
+    Ok(dirs_list)
+}
+
+fn file_name(path: &Path) -> &str {
+    path.file_name().and_then(|s| s.to_str()).unwrap_or("")
+}
+
+/// Guesses descriptor-style features from the naming conventions distributions
+/// use for their firmware images.
+fn infer_features(code: &Path, vars: &Path) -> Vec<String> {
+    let code_name = file_name(code).to_lowercase();
+    let vars_name = file_name(vars).to_lowercase();
+    let mut features = Vec::new();
+    if code_name.contains("secboot") {
+        features.push("secure-boot".to_string());
+        features.push("requires-smm".to_string());
+    }
+    if [".ms.", ".snakeoil.", ".secboot."]
+        .iter()
+        .any(|variant| vars_name.contains(variant))
+    {
+        features.push("enrolled-keys".to_string());
+    }
+    features
+}
+
+/// Split flash images only work together when they come from the same build:
+/// x86 code and vars add up to the flash size (2M or 4M), while AAVMF pads
+/// both to the same size.
+fn sizes_compatible(code: &Path, vars: &Path) -> bool {
+    match (fs::metadata(code), fs::metadata(vars)) {
+        (Ok(code), Ok(vars)) => {
+            code.len() == vars.len() || (code.len() + vars.len()).is_power_of_two()
+        }
+        _ => false,
+    }
+}
+
+/// Candidate vars templates for a code image, most specific first. Each
+/// candidate is named after the code image so variants (`_4M`, `.secboot`, ...)
+/// never get mixed.
+fn vars_candidates(code: &Path) -> Vec<PathBuf> {
+    let Some(dir) = code.parent() else {
+        return Vec::new();
+    };
+    let vars_name = file_name(code).replacen("CODE", "VARS", 1);
+    let mut names = vec![vars_name.clone()];
+    let plain = vars_name.replacen(".secboot", "", 1);
+    if plain != vars_name {
+        names.push(plain.clone());
+    }
+    if let Some(stem) = plain.strip_suffix(".fd") {
+        names.push(format!("{}.ms.fd", stem));
+        names.push(format!("{}.snakeoil.fd", stem));
+    }
+    names
+        .into_iter()
+        .map(|name| dir.join(name))
+        .filter(|vars| vars.is_file() && sizes_compatible(code, vars))
+        .collect()
+}
+
+fn scan_dir(dir: &Path) -> Vec<Firmware> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut codes: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            let name = file_name(path);
+            name.contains("CODE") && name.ends_with(".fd")
+        })
+        .collect();
+    codes.sort();
+    // Prefer the larger (4M) builds, which every current distribution ships.
+    codes.sort_by_key(|code| std::cmp::Reverse(fs::metadata(code).map(|m| m.len()).unwrap_or(0)));
+
+    codes
+        .iter()
+        .flat_map(|code| {
+            vars_candidates(code)
+                .into_iter()
+                .map(|vars| Firmware::from_paths(code.clone(), vars, "raw"))
+        })
+        .collect()
+}
+
+fn find_in_scan(
+    dirs_list: &[PathBuf],
+    query: &FirmwareQuery,
+    keep: &impl Fn(&Firmware) -> bool,
+) -> Option<Firmware> {
+    dirs_list.iter().find_map(|dir| {
+        scan_dir(dir)
+            .into_iter()
+            .find(|firmware| query.accepts(&firmware.features) && keep(firmware))
+    })
+}
+
+/// Finds the vars template belonging to a user-supplied code image.
+pub fn pair_for_code(code: &Path) -> Result<Firmware, Error> {
+    if !code.is_file() {
+        return Err(Error::Io(std::io::Error::new(
+            ErrorKind::NotFound,
+            format!("Firmware code image not found: {}", code.display()),
+        )));
+    }
+    match vars_candidates(code).into_iter().next() {
+        Some(vars) => Ok(Firmware::from_paths(code.to_path_buf(), vars, "raw")),
+        None => Err(Error::Io(std::io::Error::new(
+            ErrorKind::NotFound,
+            format!(
+                "No matching VARS template found next to {}. Specify the vars template explicitly.",
+                code.display()
+            ),
+        ))),
+    }
+}
+
+/// Locates UEFI firmware for `query`, preferring QEMU firmware descriptors and
+/// falling back to scanning well-known directories.
+pub fn find(query: &FirmwareQuery) -> Result<Firmware, Error> {
+    find_where(query, |_| true)
+}
+
+/// Like [`find`], but skips firmware that `keep` rejects.
+pub fn find_where(
+    query: &FirmwareQuery,
+    keep: impl Fn(&Firmware) -> bool,
+) -> Result<Firmware, Error> {
+    let descriptors = descriptor_dirs(query.arch);
+    if let Some(firmware) = find_in_descriptors(&descriptors, query, &keep) {
+        return Ok(firmware);
+    }
+    let scan = scan_dirs(query.arch)?;
+    if let Some(firmware) = find_in_scan(&scan, query, &keep) {
+        return Ok(firmware);
+    }
+    Err(Error::Io(std::io::Error::new(
+        ErrorKind::NotFound,
+        format!(
+            "No UEFI firmware found for {} (features: [{}]). Searched descriptors in {} and directories {}. Set {} to add a search path.",
+            query.arch,
+            query.features.join(", "),
+            display_list(&descriptors),
+            display_list(&scan),
+            FIRMWARE_PATH_ENV
+        ),
+    )))
+}
+
+fn display_list(paths: &[PathBuf]) -> String {
+    paths
+        .iter()
+        .map(|p| p.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::varstore::VarStore;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir =
+            env::temp_dir().join(format!("chromeos-launcher-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_descriptor(dir: &Path, name: &str, code: &Path, vars: &Path, features: &[&str]) {
+        let json = serde_json::json!({
+            "description": name,
+            "interface-types": ["uefi"],
+            "mapping": {
+                "device": "flash",
+                "executable": { "filename": code, "format": "raw" },
+                "nvram-template": { "filename": vars, "format": "raw" }
+            },
+            "targets": [{ "architecture": "x86_64", "machines": ["pc-i440fx-*", "pc-q35-*"] }],
+            "features": features,
+        });
+        fs::write(dir.join(name), json.to_string()).unwrap();
+    }
+
+    const PLAIN: FirmwareQuery = FirmwareQuery {
+        arch: "x86_64",
+        machine: "pc-q35",
+        features: &[],
+        excluded_features: &["requires-smm"],
+    };
+
+    fn any(_: &Firmware) -> bool {
+        true
+    }
+
+    #[test]
+    fn test_machine_matches() {
+        assert!(machine_matches("pc-q35-*", "pc-q35"));
+        assert!(machine_matches("virt-*", "virt"));
+        assert!(!machine_matches("pc-i440fx-*", "pc-q35"));
+    }
+
+    #[test]
+    fn test_descriptor_selection() {
+        let dir = scratch_dir("descriptors");
+        let code = dir.join("OVMF_CODE.fd");
+        let vars = dir.join("OVMF_VARS.fd");
+        fs::write(&code, b"").unwrap();
+        fs::write(&vars, b"").unwrap();
+        write_descriptor(
+            &dir,
+            "10-secboot.json",
+            &code,
+            &vars,
+            &["requires-smm", "secure-boot"],
+        );
+        write_descriptor(&dir, "20-plain.json", &code, &vars, &["acpi-s3"]);
+
+        let dirs_list = vec![dir.clone()];
+        let firmware = find_in_descriptors(&dirs_list, &PLAIN, &any).unwrap();
+        assert_eq!(firmware.description, "20-plain.json");
+
+        let secure = FirmwareQuery {
+            features: &["secure-boot"],
+            excluded_features: &[],
+            ..PLAIN
+        };
+        let firmware = find_in_descriptors(&dirs_list, &secure, &any).unwrap();
+        assert_eq!(firmware.description, "10-secboot.json");
+
+        let aarch64 = FirmwareQuery {
+            arch: "aarch64",
+            machine: "virt",
+            ..PLAIN
+        };
+        assert!(find_in_descriptors(&dirs_list, &aarch64, &any).is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_descriptor_shadowing() {
+        let dir = scratch_dir("shadowing");
+        let high = dir.join("etc");
+        let low = dir.join("usr");
+        fs::create_dir_all(&high).unwrap();
+        fs::create_dir_all(&low).unwrap();
+        let code = dir.join("CODE.fd");
+        fs::write(&code, b"").unwrap();
+        write_descriptor(&low, "50-ovmf.json", &code, &code, &[]);
+        fs::write(high.join("50-ovmf.json"), "{}").unwrap();
+
+        assert!(find_in_descriptors(&[high, low], &PLAIN, &any).is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn sized(dir: &Path, name: &str, len: u64) -> PathBuf {
+        let path = dir.join(name);
+        fs::File::create(&path).unwrap().set_len(len).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_scan_pairs_matching_variants() {
+        let dir = scratch_dir("scan");
+        let code_2m = sized(&dir, "OVMF_CODE.fd", 1966080);
+        let vars_2m = sized(&dir, "OVMF_VARS.fd", 131072);
+        let code_4m = sized(&dir, "OVMF_CODE_4M.fd", 3653632);
+        let secboot_4m = sized(&dir, "OVMF_CODE_4M.secboot.fd", 3653632);
+        let vars_4m = sized(&dir, "OVMF_VARS_4M.fd", 540672);
+        let ms_4m = sized(&dir, "OVMF_VARS_4M.ms.fd", 540672);
+        let dirs_list = vec![dir.clone()];
+
+        let plain = find_in_scan(&dirs_list, &PLAIN, &any).unwrap();
+        assert_eq!((plain.code, plain.vars_template), (code_4m, vars_4m));
+
+        let same_size =
+            |firmware: &Firmware| fs::metadata(&firmware.vars_template).unwrap().len() == 131072;
+        let legacy = find_in_scan(&dirs_list, &PLAIN, &same_size).unwrap();
+        assert_eq!((legacy.code, legacy.vars_template), (code_2m, vars_2m));
+
+        let secure = FirmwareQuery {
+            features: &["secure-boot", "enrolled-keys"],
+            excluded_features: &[],
+            ..PLAIN
+        };
+        let secure = find_in_scan(&dirs_list, &secure, &any).unwrap();
+        assert_eq!((secure.code, secure.vars_template), (secboot_4m, ms_4m));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_pair_for_code_rejects_mismatched_sizes() {
+        let dir = scratch_dir("pair");
+        let code = sized(&dir, "OVMF_CODE_4M.secboot.fd", 3653632);
+        sized(&dir, "OVMF_VARS.fd", 131072);
+        assert!(pair_for_code(&code).is_err());
+
+        let vars = sized(&dir, "OVMF_VARS_4M.fd", 540672);
+        assert_eq!(pair_for_code(&code).unwrap().vars_template, vars);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Parses the vars templates installed on this host, if any, so the
+    /// variable store parser also sees the distribution's own builds.
+    #[test]
+    #[ignore = "depends on the firmware installed on the host"]
+    fn test_installed_vars_templates_parse() {
+        for arch in ["x86_64", "aarch64"] {
+            let query = FirmwareQuery {
+                arch,
+                machine: "",
+                features: &[],
+                excluded_features: &[],
+            };
+            let described = load_descriptors(&descriptor_dirs(arch))
+                .into_iter()
+                .filter_map(|(_, descriptor)| descriptor.matching(&query));
+            let scanned = scan_dirs(arch)
+                .unwrap()
+                .iter()
+                .flat_map(|dir| scan_dir(dir))
+                .collect::<Vec<_>>();
+            for firmware in described.chain(scanned) {
+                if firmware.format != "raw" {
+                    continue;
+                }
+                let Ok(image) = fs::read(&firmware.vars_template) else {
+                    continue;
+                };
+                let store = VarStore::parse(image)
+                    .unwrap_or_else(|err| panic!("{}: {}", firmware.vars_template.display(), err));
+                let rewritten = VarStore::parse(store.to_bytes().unwrap()).unwrap();
+                assert_eq!(rewritten.variables, store.variables);
+            }
+        }
+    }
+}
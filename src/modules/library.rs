@@ -0,0 +1,245 @@
+use crate::modules::chromeos::{self, ImageInfo};
+use crate::modules::machine::config_dir;
+use crate::utils::error::Error;
+use crate::utils::progress::ProgressReader;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Metadata stored next to each library image as `<id>.json`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LibraryImage {
+    pub id: String,
+    pub board: String,
+    pub version: String,
+    pub channel: String,
+    pub milestone: String,
+    pub sha256: String,
+    pub size: u64,
+    /// File the image was imported from.
+    pub source: String,
+    pub added: String,
+}
+
+impl LibraryImage {
+    pub fn path(&self) -> Result<PathBuf, Error> {
+        Ok(library_dir()?.join(format!("{}.bin", self.id)))
+    }
+
+    /// Whether `reference` names this image, either by id or as
+    /// `board:version` (with or without the board's signing suffix).
+    pub fn matches(&self, reference: &str) -> bool {
+        if reference == self.id {
+            return true;
+        }
+        let Some((board, version)) = reference.split_once(':') else {
+            return false;
+        };
+        version == self.version
+            && (board == self.board || board == chromeos::short_board(&self.board))
+    }
+}
+
+/// Returns `~/.chromeos-launcher/images`, the managed image library.
+pub fn library_dir() -> Result<PathBuf, Error> {
+    Ok(config_dir()?.join("images"))
+}
+
+fn not_found(message: String) -> Error {
+    Error::Io(std::io::Error::new(ErrorKind::NotFound, message))
+}
+
+/// All library images, sorted by id.
+pub fn images() -> Result<Vec<LibraryImage>, Error> {
+    let Ok(entries) = fs::read_dir(library_dir()?) else {
+        return Ok(Vec::new());
+    };
+    let mut images = Vec::new();
+    for path in entries.flatten().map(|entry| entry.path()) {
+        if path.extension().and_then(|e| e.to_str()) != Some("json") {
+            continue;
+        }
+        let image: LibraryImage = serde_json::from_slice(&fs::read(&path)?).map_err(|err| {
+            Error::Io(std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), err),
+            ))
+        })?;
+        images.push(image);
+    }
+    images.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(images)
+}
+
+/// Looks up an image by id or `board:version`.
+pub fn find(reference: &str) -> Result<LibraryImage, Error> {
+    let images = images()?;
+    let matches: Vec<&LibraryImage> = images.iter().filter(|i| i.matches(reference)).collect();
+    match matches.as_slice() {
+        [image] => Ok((*image).clone()),
+        [] => Err(not_found(format!(
+            "No library image matches '{}'. See `images list`.",
+            reference
+        ))),
+        _ => Err(Error::Io(std::io::Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "'{}' matches several library images ({}); use the id instead.",
+                reference,
+                matches
+                    .iter()
+                    .map(|i| i.id.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        ))),
+    }
+}
+
+/// Streams `reader` into `writer` and returns the SHA-256 of the data as hex.
+fn copy_hashed(reader: impl Read, mut writer: impl Write, total: u64) -> std::io::Result<String> {
+    let mut reader = ProgressReader::new(reader, total);
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 1 << 20];
+    loop {
+        let n = reader.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+        writer.write_all(&buffer[..n])?;
+    }
+    writer.flush()?;
+    eprintln!();
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+/// Copies the inspected image at `path` into the library under `id`;
+/// `source` is what the user imported (possibly an archive `path` came from).
+pub fn add(
+    path: &Path,
+    source: &str,
+    info: &ImageInfo,
+    id: &str,
+    added: String,
+) -> Result<LibraryImage, Error> {
+    if id.is_empty() || id.contains(['/', ':']) || id.starts_with('.') {
+        return Err(Error::Io(std::io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("Invalid image id '{}'", id),
+        )));
+    }
+    if images()?.iter().any(|image| image.id == id) {
+        return Err(Error::Io(std::io::Error::new(
+            ErrorKind::AlreadyExists,
+            format!("Library image '{}' already exists", id),
+        )));
+    }
+    let dir = library_dir()?;
+    fs::create_dir_all(&dir)?;
+
+    let target = dir.join(format!("{}.bin", id));
+    let partial = dir.join(format!("{}.bin.partial", id));
+    let copied =
+        File::create(&partial).and_then(|file| copy_hashed(File::open(path)?, file, info.size));
+    let sha256 = match copied {
+        Ok(sha256) => sha256,
+        Err(err) => {
+            let _ = fs::remove_file(&partial);
+            return Err(err.into());
+        }
+    };
+    if let Some(existing) = images()?.into_iter().find(|i| i.sha256 == sha256) {
+        fs::remove_file(&partial)?;
+        return Err(Error::Io(std::io::Error::new(
+            ErrorKind::AlreadyExists,
+            format!("This image is already in the library as '{}'", existing.id),
+        )));
+    }
+
+    let release = &info.release;
+    let image = LibraryImage {
+        id: id.to_string(),
+        board: release.board.clone(),
+        version: release.version.clone(),
+        channel: release.short_channel().to_string(),
+        milestone: release.milestone.clone(),
+        sha256,
+        size: info.size,
+        source: source.to_string(),
+        added,
+    };
+    fs::rename(&partial, &target)?;
+    save(&image)?;
+    Ok(image)
+}
+
+fn save(image: &LibraryImage) -> Result<(), Error> {
+    let json = serde_json::to_string_pretty(image).map_err(std::io::Error::other)?;
+    fs::write(library_dir()?.join(format!("{}.json", image.id)), json)?;
+    Ok(())
+}
+
+pub fn remove(image: &LibraryImage) -> Result<(), Error> {
+    let path = image.path()?;
+    if path.exists() {
+        fs::remove_file(path)?;
+    }
+    fs::remove_file(library_dir()?.join(format!("{}.json", image.id)))?;
+    Ok(())
+}
+
+/// Recomputes the image's checksum; `Ok(false)` means it no longer matches.
+pub fn verify(image: &LibraryImage) -> Result<bool, Error> {
+    let path = image.path()?;
+    let file =
+        File::open(&path).map_err(|_| not_found(format!("{} is missing", path.display())))?;
+    let size = file.metadata()?.len();
+    Ok(copy_hashed(file, std::io::sink(), size)? == image.sha256)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(id: &str, board: &str, version: &str) -> LibraryImage {
+        LibraryImage {
+            id: id.to_string(),
+            board: board.to_string(),
+            version: version.to_string(),
+            channel: "stable".to_string(),
+            milestone: "121".to_string(),
+            sha256: String::new(),
+            size: 0,
+            source: String::new(),
+            added: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_matches() {
+        let flex = image("reven-15917.71.0", "reven-signed-mp-v2keys", "15917.71.0");
+        assert!(flex.matches("reven-15917.71.0"));
+        assert!(flex.matches("reven:15917.71.0"));
+        assert!(flex.matches("reven-signed-mp-v2keys:15917.71.0"));
+        assert!(!flex.matches("reven:15917.72.0"));
+        assert!(!flex.matches("octopus:15917.71.0"));
+    }
+
+    #[test]
+    fn test_copy_hashed() {
+        let mut out = Vec::new();
+        let sha = copy_hashed(&b"abc"[..], &mut out, 3).unwrap();
+        assert_eq!(out, b"abc");
+        assert_eq!(
+            sha,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
@@ -0,0 +1,765 @@
+use crate::modules::firmware::{self, Firmware, FirmwareType};
+use crate::modules::network::{self, NetBackend, NetworkMode, NicModel, PortForward};
+use crate::modules::qemu::{
+    AudioBackend, AudioDevice, DisplayBackend, Gpu, MediaAttach, QemuConfig,
+};
+use crate::utils::error::Error;
+use crate::utils::resource::{ResourceValue, parse_disk_size};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Returns `~/.chromeos-launcher`, the root of all launcher state.
+pub fn config_dir() -> Result<PathBuf, Error> {
+    Ok(dirs::home_dir()
+        .ok_or_else(|| {
+            Error::Io(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "Home directory not found",
+            ))
+        })?
+        .join(".chromeos-launcher"))
+}
+
+pub fn machines_dir() -> Result<PathBuf, Error> {
+    Ok(config_dir()?.join("machines"))
+}
+
+/// Files in `machines/` that belong to a machine rather than define one.
+const MACHINE_FILE_SUFFIXES: [&str; 8] = [
+    "img", "vars", "nvram", "release", "key", "log", "wav", "bak",
+];
+
+/// Names of all configured machines, sorted.
+pub fn machine_names() -> Result<Vec<String>, Error> {
+    machine_names_in(&machines_dir()?)
+}
+
+fn machine_names_in(dir: &Path) -> Result<Vec<String>, Error> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Ok(Vec::new());
+    };
+    let mut names: Vec<String> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .filter(|path| {
+            path.extension()
+                .and_then(|e| e.to_str())
+                .is_none_or(|e| !MACHINE_FILE_SUFFIXES.contains(&e))
+        })
+        .filter_map(|path| Some(path.file_name()?.to_str()?.to_string()))
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+/// A machine setting that can be changed with `config set`.
+pub struct Setting {
+    pub key: &'static str,
+    pub description: &'static str,
+    validate: fn(&str) -> Result<(), String>,
+}
+
+fn any_value(_: &str) -> Result<(), String> {
+    Ok(())
+}
+
+fn resource_value(value: &str) -> Result<(), String> {
+    ResourceValue::from_str(value).map(|_| ())
+}
+
+fn bool_value(value: &str) -> Result<(), String> {
+    value
+        .parse::<bool>()
+        .map(|_| ())
+        .map_err(|_| format!("Expected true or false, got '{}'", value))
+}
+
+fn disk_format(value: &str) -> Result<(), String> {
+    match value {
+        "raw" | "qcow2" => Ok(()),
+        _ => Err(format!("Expected raw or qcow2, got '{}'", value)),
+    }
+}
+
+fn existing_file(value: &str) -> Result<(), String> {
+    if Path::new(value).exists() {
+        Ok(())
+    } else {
+        Err(format!("File not found: {}", value))
+    }
+}
+
+fn vnc_display(value: &str) -> Result<(), String> {
+    match value.rsplit_once(':') {
+        Some((_, display)) if display.parse::<u16>().is_ok() => Ok(()),
+        _ => Err(format!(
+            "Expected host:display such as 127.0.0.1:0, got '{}'",
+            value
+        )),
+    }
+}
+
+/// Parses a `WIDTHxHEIGHT` guest resolution such as `1920x1080`.
+pub fn parse_resolution(value: &str) -> Result<(u32, u32), String> {
+    value
+        .split_once('x')
+        .and_then(|(w, h)| Some((w.parse::<u32>().ok()?, h.parse::<u32>().ok()?)))
+        .filter(|&(w, h)| (1..=16384).contains(&w) && (1..=16384).contains(&h))
+        .ok_or_else(|| format!("Expected WIDTHxHEIGHT such as 1920x1080, got '{}'", value))
+}
+
+fn max_outputs(value: &str) -> Result<(), String> {
+    match value.parse::<u32>() {
+        Ok(1..=16) => Ok(()),
+        _ => Err(format!("Expected a number from 1 to 16, got '{}'", value)),
+    }
+}
+
+pub const SETTINGS: &[Setting] = &[
+    Setting {
+        key: "MEMORY",
+        description: "guest memory, e.g. 8G or 50%",
+        validate: resource_value,
+    },
+    Setting {
+        key: "CPU_CORES",
+        description: "virtual CPUs, e.g. 4 or 50%",
+        validate: resource_value,
+    },
+    Setting {
+        key: "CPU_MODEL",
+        description: "QEMU -cpu model",
+        validate: any_value,
+    },
+    Setting {
+        key: "DISK_PATH",
+        description: "disk image or block device",
+        validate: any_value,
+    },
+    Setting {
+        key: "HDD_SIZE",
+        description: "size used when the disk has to be created, e.g. 64G",
+        validate: |value| parse_disk_size(value).map(|_| ()),
+    },
+    Setting {
+        key: "DISK_FORMAT",
+        description: "raw or qcow2",
+        validate: disk_format,
+    },
+    Setting {
+        key: "FIRMWARE",
+        description: "uefi or bios",
+        validate: |value| FirmwareType::from_str(value).map(|_| ()),
+    },
+    Setting {
+        key: "SECURE_BOOT",
+        description: "true or false",
+        validate: bool_value,
+    },
+    Setting {
+        key: "MEDIA_ATTACH",
+        description: "usb, ide-cd or virtio",
+        validate: |value| MediaAttach::from_str(value).map(|_| ()),
+    },
+    Setting {
+        key: "RECOVERY_PATH",
+        description: "recovery image attached by `run --with-recovery`",
+        validate: existing_file,
+    },
+    Setting {
+        key: "BOARD",
+        description: "board recovery images must match",
+        validate: any_value,
+    },
+    Setting {
+        key: "DISPLAY",
+        description: "sdl, gtk, spice-app, vnc, egl-headless or none",
+        validate: |value| DisplayBackend::from_str(value).map(|_| ()),
+    },
+    Setting {
+        key: "DISPLAY_GL",
+        description: "OpenGL in the sdl, gtk and spice-app windows (default true)",
+        validate: bool_value,
+    },
+    Setting {
+        key: "VNC_DISPLAY",
+        description: "VNC listen address as host:display (default 127.0.0.1:0)",
+        validate: vnc_display,
+    },
+    Setting {
+        key: "GPU",
+        description: "std, virtio, virtio-gl, rutabaga, venus or none (default: virtio-gl with an OpenGL display)",
+        validate: |value| Gpu::from_str(value).map(|_| ()),
+    },
+    Setting {
+        key: "RESOLUTION",
+        description: "guest screen size, e.g. 1920x1080 or 1080x1920 (default 1280x800)",
+        validate: |value| parse_resolution(value).map(|_| ()),
+    },
+    Setting {
+        key: "MAX_OUTPUTS",
+        description: "number of guest monitors (default 1)",
+        validate: max_outputs,
+    },
+    Setting {
+        key: "FULL_SCREEN",
+        description: "start the sdl or gtk window full screen (true or false)",
+        validate: bool_value,
+    },
+    Setting {
+        key: "WINDOW_TITLE",
+        description: "title of the QEMU window (default: the machine name)",
+        validate: any_value,
+    },
+    Setting {
+        key: "AUDIO",
+        description: "pipewire, pulseaudio, alsa, sdl, wav or none (default sdl)",
+        validate: |value| AudioBackend::from_str(value).map(|_| ()),
+    },
+    Setting {
+        key: "AUDIO_DEVICE",
+        description: "intel-hda, ac97 or virtio-sound (default intel-hda)",
+        validate: |value| AudioDevice::from_str(value).map(|_| ()),
+    },
+    Setting {
+        key: "AUDIO_INPUT",
+        description: "give the guest a microphone (true or false)",
+        validate: bool_value,
+    },
+    Setting {
+        key: "AUDIO_WAV_PATH",
+        description: "file the wav audio backend writes (default machines/<name>.wav)",
+        validate: any_value,
+    },
+    Setting {
+        key: "KERNEL",
+        description: "kernel booted directly instead of through the firmware",
+        validate: existing_file,
+    },
+    Setting {
+        key: "INITRD",
+        description: "initrd for the directly booted kernel",
+        validate: existing_file,
+    },
+    Setting {
+        key: "APPEND",
+        description: "command line for the directly booted kernel",
+        validate: any_value,
+    },
+    Setting {
+        key: "NETWORK",
+        description: "user, bridge, tap or none (default user)",
+        validate: |value| NetworkMode::from_str(value).map(|_| ()),
+    },
+    Setting {
+        key: "BRIDGE",
+        description: "host bridge joined with network bridge (default br0)",
+        validate: any_value,
+    },
+    Setting {
+        key: "BRIDGE_HELPER",
+        description: "qemu-bridge-helper to use instead of the installed one",
+        validate: existing_file,
+    },
+    Setting {
+        key: "TAP",
+        description: "tap interface used with network tap (default tap0)",
+        validate: any_value,
+    },
+    Setting {
+        key: "NIC_MODEL",
+        description: "virtio-net or e1000 (default virtio-net)",
+        validate: |value| NicModel::from_str(value).map(|_| ()),
+    },
+    Setting {
+        key: "PORT_FORWARDS",
+        description: "name:tcp|udp:[address:]host-port:guest-port,... (see `port-forward`)",
+        validate: |value| network::parse_port_forwards(value).map(|_| ()),
+    },
+    Setting {
+        key: "SSH_KEY",
+        description: "private key for logging in as root over SSH",
+        validate: existing_file,
+    },
+];
+
+/// Finds a setting by key, accepting `recovery-path` for `RECOVERY_PATH`.
+pub fn setting(name: &str) -> Result<&'static Setting, Error> {
+    let key = name.replace('-', "_").to_uppercase();
+    SETTINGS
+        .iter()
+        .find(|setting| setting.key == key)
+        .ok_or_else(|| {
+            Error::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "Unknown setting '{}'. Known settings: {}",
+                    name,
+                    SETTINGS
+                        .iter()
+                        .map(|s| s.key.to_lowercase().replace('_', "-"))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            ))
+        })
+}
+
+impl Setting {
+    pub fn validate(&self, value: &str) -> Result<(), Error> {
+        (self.validate)(value).map_err(|err| {
+            Error::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Invalid value for {}: {}", self.key, err),
+            ))
+        })
+    }
+}
+
+/// A machine definition stored as `KEY=VALUE` lines in `machines/<name>`.
+///
+/// Keys keep their original order so rewriting a file only changes the lines
+/// that were actually modified.
+pub struct MachineConfig {
+    pub name: String,
+    path: PathBuf,
+    entries: Vec<(String, String)>,
+}
+
+impl MachineConfig {
+    /// Loads the configuration of an existing machine.
+    pub fn load(name: &str) -> Result<Self, Error> {
+        let path = machines_dir()?.join(name);
+        if !path.exists() {
+            return Err(Error::Io(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("Configuration for '{}' not found.", name),
+            )));
+        }
+        let content = fs::read_to_string(&path)?;
+        let mut config = Self {
+            name: name.to_string(),
+            path,
+            entries: parse(&content),
+        };
+        config.migrate();
+        Ok(config)
+    }
+
+    /// Rewrites settings that have been replaced. This only changes the
+    /// loaded entries; the file is updated the next time a command saves it.
+    ///
+    /// `USE_3D_ACCEL` was inverted: `true` ran `-vga virtio` and anything
+    /// else `virtio-vga-gl`, so it becomes the `GPU` that was actually used.
+    fn migrate(&mut self) {
+        if let Some(use_3d_accel) = self.get("USE_3D_ACCEL").map(str::to_string) {
+            if self.get("GPU").is_none() {
+                let gpu = match use_3d_accel.parse::<bool>() {
+                    Ok(true) => Gpu::Virtio,
+                    _ => Gpu::VirtioGl,
+                };
+                self.set("GPU", gpu);
+            }
+            self.remove("USE_3D_ACCEL");
+        }
+    }
+
+    /// Starts an empty configuration for a machine that must not exist yet.
+    pub fn create(name: &str) -> Result<Self, Error> {
+        let machines_dir = machines_dir()?;
+        fs::create_dir_all(&machines_dir)?;
+        let path = machines_dir.join(name);
+        if path.exists() {
+            return Err(Error::Io(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("VM '{}' already exists", name),
+            )));
+        }
+        Ok(Self {
+            name: name.to_string(),
+            path,
+            entries: Vec::new(),
+        })
+    }
+
+    /// A configuration that only exists in memory.
+    #[cfg(test)]
+    pub(crate) fn from_content(name: &str, content: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            path: PathBuf::from(name),
+            entries: parse(content),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the value of `key`, treating empty values as unset.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+            .filter(|v| !v.is_empty())
+    }
+
+    /// Returns `key` parsed as a boolean, defaulting to `false`.
+    pub fn get_bool(&self, key: &str) -> bool {
+        self.get(key)
+            .and_then(|s| s.parse::<bool>().ok())
+            .unwrap_or(false)
+    }
+
+    pub fn set(&mut self, key: &str, value: impl ToString) {
+        let value = value.to_string();
+        match self.entries.iter_mut().find(|(k, _)| k == key) {
+            Some(entry) => entry.1 = value,
+            None => self.entries.push((key.to_string(), value)),
+        }
+    }
+
+    /// All stored settings in file order.
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        self.entries.retain(|(k, _)| k != key);
+    }
+
+    pub fn save(&self) -> Result<(), Error> {
+        let content: String = self
+            .entries
+            .iter()
+            .map(|(k, v)| format!("{}={}\n", k, v))
+            .collect();
+        fs::write(&self.path, content)?;
+        Ok(())
+    }
+
+    /// Path of the machine's disk image, `machines/<name>.img` unless configured.
+    pub fn disk_path(&self) -> Result<PathBuf, Error> {
+        match self.get("DISK_PATH") {
+            Some(path) => Ok(PathBuf::from(path)),
+            None => Ok(machines_dir()?.join(format!("{}.img", self.name))),
+        }
+    }
+
+    /// Image format of the disk, `raw` unless configured.
+    pub fn disk_format(&self) -> &str {
+        self.get("DISK_FORMAT").unwrap_or("raw")
+    }
+
+    /// Path of the disk for reading its partitions directly, which only
+    /// works for raw images: a qcow2 disk is only readable through QEMU.
+    pub fn raw_disk_path(&self) -> Result<PathBuf, Error> {
+        match self.disk_format() {
+            "raw" => self.disk_path(),
+            format => Err(Error::Io(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                format!("{} disks can't be read directly", format),
+            ))),
+        }
+    }
+
+    /// Path of the machine's writable UEFI variable store.
+    pub fn vars_path(&self) -> Result<PathBuf, Error> {
+        Ok(machines_dir()?.join(format!("{}.vars", self.name)))
+    }
+
+    /// Where the release read from the machine's disk is cached.
+    pub fn release_cache_path(&self) -> Result<PathBuf, Error> {
+        Ok(machines_dir()?.join(format!("{}.release", self.name)))
+    }
+
+    /// Where the machine's copy of the testing SSH key is kept.
+    pub fn ssh_key_path(&self) -> Result<PathBuf, Error> {
+        Ok(machines_dir()?.join(format!("{}.key", self.name)))
+    }
+
+    /// Where `run --debug-log` makes QEMU write its debug output.
+    pub fn debug_log_path(&self) -> Result<PathBuf, Error> {
+        Ok(machines_dir()?.join(format!("{}.log", self.name)))
+    }
+
+    /// The `FIRMWARE` setting; machines created before it existed are UEFI.
+    pub fn firmware_type(&self) -> Result<FirmwareType, Error> {
+        match self.get("FIRMWARE") {
+            Some(value) => Ok(FirmwareType::from_str(value)?),
+            None => Ok(FirmwareType::Uefi),
+        }
+    }
+
+    /// The `MEDIA_ATTACH` setting for install and recovery images.
+    pub fn media_attach(&self) -> Result<MediaAttach, Error> {
+        match self.get("MEDIA_ATTACH") {
+            Some(value) => Ok(MediaAttach::from_str(value)?),
+            None => Ok(MediaAttach::default()),
+        }
+    }
+
+    /// The `DISPLAY` setting.
+    pub fn display(&self) -> Result<DisplayBackend, Error> {
+        match self.get("DISPLAY") {
+            Some(value) => Ok(DisplayBackend::from_str(value)?),
+            None => Ok(DisplayBackend::default()),
+        }
+    }
+
+    /// The `DISPLAY_GL` setting, which defaults to `true`.
+    pub fn display_gl(&self) -> bool {
+        self.get("DISPLAY_GL") != Some("false")
+    }
+
+    /// The `GPU` setting; `None` leaves the choice to the display.
+    pub fn gpu(&self) -> Result<Option<Gpu>, Error> {
+        match self.get("GPU") {
+            Some(value) => Ok(Some(Gpu::from_str(value)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// The `AUDIO` setting.
+    pub fn audio(&self) -> Result<AudioBackend, Error> {
+        match self.get("AUDIO") {
+            Some(value) => Ok(AudioBackend::from_str(value)?),
+            None => Ok(AudioBackend::default()),
+        }
+    }
+
+    /// The `AUDIO_DEVICE` setting.
+    pub fn audio_device(&self) -> Result<AudioDevice, Error> {
+        match self.get("AUDIO_DEVICE") {
+            Some(value) => Ok(AudioDevice::from_str(value)?),
+            None => Ok(AudioDevice::default()),
+        }
+    }
+
+    /// Where the wav audio backend records, `machines/<name>.wav` unless
+    /// configured.
+    pub fn audio_wav_path(&self) -> Result<PathBuf, Error> {
+        match self.get("AUDIO_WAV_PATH") {
+            Some(path) => Ok(PathBuf::from(path)),
+            None => Ok(machines_dir()?.join(format!("{}.wav", self.name))),
+        }
+    }
+
+    /// The `NETWORK` setting.
+    pub fn network_mode(&self) -> Result<NetworkMode, Error> {
+        match self.get("NETWORK") {
+            Some(value) => Ok(NetworkMode::from_str(value)?),
+            None => Ok(NetworkMode::default()),
+        }
+    }
+
+    /// Resolves the `NETWORK` setting for a launch, checking that the host
+    /// side of a bridge or tap network is usable.
+    pub fn net_backend(&self) -> Result<NetBackend, Error> {
+        match self.network_mode()? {
+            NetworkMode::User => Ok(NetBackend::User(self.port_forwards()?)),
+            NetworkMode::Bridge => network::check_bridge(
+                self.get("BRIDGE").unwrap_or("br0"),
+                self.get("BRIDGE_HELPER"),
+            ),
+            NetworkMode::Tap => network::check_tap(self.get("TAP").unwrap_or("tap0")),
+            NetworkMode::None => Ok(NetBackend::None),
+        }
+    }
+
+    /// The `NIC_MODEL` setting.
+    pub fn nic_model(&self) -> Result<NicModel, Error> {
+        match self.get("NIC_MODEL") {
+            Some(value) => Ok(NicModel::from_str(value)?),
+            None => Ok(NicModel::default()),
+        }
+    }
+
+    /// The rules in the `PORT_FORWARDS` setting.
+    pub fn port_forwards(&self) -> Result<Vec<PortForward>, Error> {
+        let value = self.get("PORT_FORWARDS").unwrap_or_default();
+        network::parse_port_forwards(value).map_err(|err| {
+            Error::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Invalid PORT_FORWARDS for '{}': {}", self.name, err),
+            ))
+        })
+    }
+
+    /// Replaces the `PORT_FORWARDS` setting, dropping it when empty.
+    pub fn set_port_forwards(&mut self, forwards: &[PortForward]) {
+        if forwards.is_empty() {
+            self.remove("PORT_FORWARDS");
+        } else {
+            self.set("PORT_FORWARDS", network::format_port_forwards(forwards));
+        }
+    }
+
+    /// The `RESOLUTION` setting, 1280x800 unless configured.
+    pub fn resolution(&self) -> Result<(u32, u32), Error> {
+        match self.get("RESOLUTION") {
+            Some(value) => Ok(parse_resolution(value)?),
+            None => Ok((1280, 800)),
+        }
+    }
+
+    /// Fails for machines that have no UEFI variable store.
+    pub fn ensure_uefi(&self) -> Result<(), Error> {
+        if self.firmware_type()? == FirmwareType::Bios {
+            return Err(Error::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "'{}' boots with legacy BIOS and has no UEFI NVRAM.",
+                    self.name
+                ),
+            )));
+        }
+        Ok(())
+    }
+
+    /// Directory holding timestamped copies of the variable store.
+    pub fn nvram_backup_dir(&self) -> Result<PathBuf, Error> {
+        Ok(machines_dir()?.join(format!("{}.nvram", self.name)))
+    }
+
+    /// Directory holding copies of a block-device disk taken before a wipe.
+    pub fn disk_backup_dir(&self) -> Result<PathBuf, Error> {
+        Ok(machines_dir()?.join(format!("{}.backups", self.name)))
+    }
+
+    /// Returns the firmware pair recorded for this machine.
+    ///
+    /// Machines created before the pair was recorded get one selected now, and
+    /// the choice is written back so later runs keep using the same files. A
+    /// machine that already has a variable store gets a pair whose template
+    /// has the same size, since other builds can't read it.
+    pub fn firmware(&mut self, qemu_config: &QemuConfig) -> Result<Firmware, Error> {
+        self.select_firmware(qemu_config, true)
+    }
+
+    /// Like [`firmware`](Self::firmware), for callers that are about to
+    /// replace the variable store with a fresh copy of the template.
+    pub fn firmware_for_new_vars(&mut self, qemu_config: &QemuConfig) -> Result<Firmware, Error> {
+        self.select_firmware(qemu_config, false)
+    }
+
+    fn select_firmware(
+        &mut self,
+        qemu_config: &QemuConfig,
+        keep_vars: bool,
+    ) -> Result<Firmware, Error> {
+        let secure_boot = self.get_bool("SECURE_BOOT");
+        let firmware = match (self.get("OVMF_CODE"), self.get("OVMF_VARS")) {
+            (Some(code), Some(vars)) => {
+                let mut firmware = Firmware::from_paths(
+                    PathBuf::from(code),
+                    PathBuf::from(vars),
+                    self.get("OVMF_FORMAT").unwrap_or("raw"),
+                );
+                if let Some(features) = self.get("OVMF_FEATURES") {
+                    firmware.features = features.split(',').map(str::to_string).collect();
+                }
+                if let Some(missing) = [&firmware.code, &firmware.vars_template]
+                    .into_iter()
+                    .find(|path| !path.exists())
+                {
+                    return Err(Error::Io(std::io::Error::new(
+                        std::io::ErrorKind::NotFound,
+                        format!(
+                            "Firmware file '{}' recorded for '{}' no longer exists. Reinstall it, or remove the OVMF_CODE and OVMF_VARS lines from {} to select new firmware (the machine's NVRAM will have to be reset).",
+                            missing.display(),
+                            self.name,
+                            self.path.display()
+                        ),
+                    )));
+                }
+                if secure_boot && !firmware.has_feature("secure-boot") {
+                    return Err(Error::Io(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!(
+                            "Secure Boot is enabled for '{}' but the recorded firmware '{}' does not support it. Remove the OVMF_CODE and OVMF_VARS lines from {} to select Secure Boot firmware.",
+                            self.name,
+                            firmware.code.display(),
+                            self.path.display()
+                        ),
+                    )));
+                }
+                return Ok(firmware);
+            }
+            (Some(code), None) => firmware::pair_for_code(Path::new(code))?,
+            _ => {
+                let vars = self.vars_path()?;
+                match fs::metadata(&vars) {
+                    Ok(existing) if keep_vars => firmware::find_where(
+                        &qemu_config.firmware_query(secure_boot),
+                        |firmware| {
+                            fs::metadata(&firmware.vars_template)
+                                .is_ok_and(|template| template.len() == existing.len())
+                        },
+                    )
+                    .map_err(|_| {
+                        Error::Io(std::io::Error::new(
+                            std::io::ErrorKind::NotFound,
+                            format!(
+                                "No installed firmware has a vars template the size of {} ({} bytes), so the firmware '{}' was created with can't be found. Run `chromeos-launcher nvram reset {}` to start over with the installed firmware (this clears the machine's UEFI settings).",
+                                vars.display(),
+                                existing.len(),
+                                self.name,
+                                self.name
+                            ),
+                        ))
+                    })?,
+                    _ => qemu_config.find_firmware(secure_boot)?,
+                }
+            }
+        };
+        self.set_firmware(&firmware);
+        self.save()?;
+        Ok(firmware)
+    }
+
+    pub fn set_firmware(&mut self, firmware: &Firmware) {
+        self.set("OVMF_CODE", firmware.code.display());
+        self.set("OVMF_VARS", firmware.vars_template.display());
+        self.set("OVMF_FORMAT", &firmware.format);
+        self.set("OVMF_FEATURES", firmware.features.join(","));
+    }
+}
+
+fn parse(content: &str) -> Vec<(String, String)> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.splitn(2, '=');
+            Some((
+                parts.next()?.trim().to_string(),
+                parts.next()?.trim().trim_matches('\'').to_string(),
+            ))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_machine_names_skip_machine_files() {
+        let dir =
+            std::env::temp_dir().join(format!("chromeos-launcher-machines-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("cros.nvram")).unwrap();
+        fs::create_dir_all(dir.join("cros.backups")).unwrap();
+        fs::write(dir.join("cros"), "VM_NAME=cros\n").unwrap();
+        fs::write(dir.join("cros.img"), [0u8; 16]).unwrap();
+        fs::write(dir.join("cros.img.20260101-000000.bak"), [0xffu8; 16]).unwrap();
+        fs::write(dir.join("cros.backups/20260101-000000.img"), [0xffu8; 16]).unwrap();
+        fs::write(dir.join("test-vm"), "VM_NAME=test-vm\n").unwrap();
+
+        assert_eq!(machine_names_in(&dir).unwrap(), ["cros", "test-vm"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
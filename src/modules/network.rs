@@ -0,0 +1,545 @@
+use crate::utils::error::Error;
+use std::fmt;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+
+/// How the guest is connected to the network.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum NetworkMode {
+    /// QEMU's user-mode (slirp) network with port forwarding
+    #[default]
+    User,
+    /// attached to a host bridge through qemu-bridge-helper
+    Bridge,
+    /// an existing tap interface
+    Tap,
+    /// no network card
+    None,
+}
+
+impl FromStr for NetworkMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "user" => Ok(NetworkMode::User),
+            "bridge" => Ok(NetworkMode::Bridge),
+            "tap" => Ok(NetworkMode::Tap),
+            "none" => Ok(NetworkMode::None),
+            _ => Err(format!(
+                "Unknown network mode: {} (expected user, bridge, tap or none)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for NetworkMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NetworkMode::User => write!(f, "user"),
+            NetworkMode::Bridge => write!(f, "bridge"),
+            NetworkMode::Tap => write!(f, "tap"),
+            NetworkMode::None => write!(f, "none"),
+        }
+    }
+}
+
+/// The network card the guest sees.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum NicModel {
+    #[default]
+    VirtioNet,
+    E1000,
+}
+
+impl FromStr for NicModel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "virtio-net" | "virtio" => Ok(NicModel::VirtioNet),
+            "e1000" => Ok(NicModel::E1000),
+            _ => Err(format!(
+                "Unknown NIC model: {} (expected virtio-net or e1000)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for NicModel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NicModel::VirtioNet => write!(f, "virtio-net"),
+            NicModel::E1000 => write!(f, "e1000"),
+        }
+    }
+}
+
+impl NicModel {
+    fn device(&self) -> &'static str {
+        match self {
+            NicModel::VirtioNet => "virtio-net-pci",
+            NicModel::E1000 => "e1000",
+        }
+    }
+}
+
+/// A named rule forwarding a host port to a guest port in user-mode
+/// networking.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PortForward {
+    pub name: String,
+    /// `tcp` or `udp`
+    pub protocol: String,
+    /// Host address to listen on, 127.0.0.1 unless given.
+    pub host_addr: IpAddr,
+    pub host_port: u16,
+    pub guest_port: u16,
+}
+
+/// The host address used when a rule does not name one.
+pub const DEFAULT_HOST_ADDR: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+impl PortForward {
+    /// The host side of the rule, with IPv6 addresses in brackets.
+    pub fn host(&self) -> SocketAddr {
+        SocketAddr::new(self.host_addr, self.host_port)
+    }
+
+    /// The value of QEMU's `hostfwd` option.
+    pub fn hostfwd(&self) -> String {
+        format!("{}:{}-:{}", self.protocol, self.host(), self.guest_port)
+    }
+
+    /// Whether both rules claim the same host port.
+    pub fn conflicts_with(&self, other: &PortForward) -> bool {
+        self.protocol == other.protocol
+            && self.host_port == other.host_port
+            && (self.host_addr == other.host_addr
+                || self.host_addr.is_unspecified()
+                || other.host_addr.is_unspecified())
+    }
+}
+
+impl FromStr for PortForward {
+    type Err = String;
+
+    /// Parses `name:protocol:[host_addr:]host_port:guest_port`; an IPv6
+    /// `host_addr` may be written in brackets.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "Invalid port forward '{}' (expected name:tcp|udp:[address:]host-port:guest-port)",
+                s
+            )
+        };
+        let mut fields = s.splitn(3, ':');
+        let (Some(name), Some(protocol), Some(rest)) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            return Err(invalid());
+        };
+        let mut ports = rest.rsplitn(3, ':');
+        let (Some(guest_port), Some(host_port)) = (ports.next(), ports.next()) else {
+            return Err(invalid());
+        };
+        let host_addr = match ports.next() {
+            Some(addr) => parse_host_addr(addr)?,
+            None => DEFAULT_HOST_ADDR,
+        };
+        check_name(name)?;
+        if protocol != "tcp" && protocol != "udp" {
+            return Err(format!(
+                "Invalid protocol '{}' (expected tcp or udp)",
+                protocol
+            ));
+        }
+        let port = |value: &str| match value.parse::<u16>() {
+            Ok(port) if port > 0 => Ok(port),
+            _ => Err(format!("Invalid port '{}' in '{}'", value, s)),
+        };
+        Ok(PortForward {
+            name: name.to_string(),
+            protocol: protocol.to_string(),
+            host_addr,
+            host_port: port(host_port)?,
+            guest_port: port(guest_port)?,
+        })
+    }
+}
+
+impl fmt::Display for PortForward {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}:{}",
+            self.name,
+            self.protocol,
+            self.host(),
+            self.guest_port
+        )
+    }
+}
+
+/// Rule names end up in the comma-separated `PORT_FORWARDS` setting.
+pub fn check_name(name: &str) -> Result<(), String> {
+    if !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        Ok(())
+    } else {
+        Err(format!(
+            "Invalid rule name '{}' (use letters, digits, '-' and '_')",
+            name
+        ))
+    }
+}
+
+/// Parses a host address, accepting IPv6 addresses with or without brackets.
+fn parse_host_addr(addr: &str) -> Result<IpAddr, String> {
+    let bare = addr
+        .strip_prefix('[')
+        .and_then(|addr| addr.strip_suffix(']'))
+        .unwrap_or(addr);
+    bare.parse()
+        .map_err(|_| format!("Invalid host address '{}'", addr))
+}
+
+/// Parses the comma-separated `PORT_FORWARDS` setting, rejecting duplicate
+/// names and host ports.
+pub fn parse_port_forwards(value: &str) -> Result<Vec<PortForward>, String> {
+    let mut forwards: Vec<PortForward> = Vec::new();
+    for rule in value.split(',').filter(|rule| !rule.is_empty()) {
+        let forward = PortForward::from_str(rule)?;
+        if let Some(other) = forwards
+            .iter()
+            .find(|other| other.name == forward.name || other.conflicts_with(&forward))
+        {
+            return Err(format!(
+                "Port forward '{}' clashes with '{}'",
+                forward.name, other.name
+            ));
+        }
+        forwards.push(forward);
+    }
+    Ok(forwards)
+}
+
+pub fn format_port_forwards(forwards: &[PortForward]) -> String {
+    forwards
+        .iter()
+        .map(PortForward::to_string)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// The guest's network as resolved for a launch: the configured
+/// [`NetworkMode`] together with what that mode needs on the host.
+pub enum NetBackend {
+    User(Vec<PortForward>),
+    Bridge { name: String, helper: PathBuf },
+    Tap { name: String },
+    None,
+}
+
+/// QEMU arguments for the guest's network.
+pub fn args(backend: &NetBackend, nic: NicModel) -> Vec<String> {
+    let netdev = match backend {
+        NetBackend::None => return vec!["-nic".to_string(), "none".to_string()],
+        NetBackend::User(forwards) => {
+            let mut netdev = "user,id=net0".to_string();
+            for forward in forwards {
+                netdev.push_str(&format!(",hostfwd={}", forward.hostfwd()));
+            }
+            netdev
+        }
+        NetBackend::Bridge { name, helper } => {
+            format!("bridge,id=net0,br={},helper={}", name, helper.display())
+        }
+        NetBackend::Tap { name } => {
+            format!("tap,id=net0,ifname={},script=no,downscript=no", name)
+        }
+    };
+    vec![
+        "-netdev".to_string(),
+        netdev,
+        "-device".to_string(),
+        format!("{},netdev=net0", nic.device()),
+    ]
+}
+
+/// Where distributions install qemu-bridge-helper.
+const BRIDGE_HELPERS: [&str; 3] = [
+    "/usr/lib/qemu/qemu-bridge-helper",
+    "/usr/libexec/qemu-bridge-helper",
+    "/usr/lib/qemu-bridge-helper",
+];
+
+/// The ACL qemu-bridge-helper consults before attaching to a bridge.
+const BRIDGE_CONF: &str = "/etc/qemu/bridge.conf";
+
+fn unavailable(message: String) -> Error {
+    Error::Io(std::io::Error::new(
+        std::io::ErrorKind::PermissionDenied,
+        message,
+    ))
+}
+
+/// The effective user id, read from the owner of `/proc/self`.
+fn current_uid() -> Option<u32> {
+    fs::metadata("/proc/self").ok().map(|m| m.uid())
+}
+
+/// Verifies that QEMU, running as the current user, can attach to `bridge`
+/// through qemu-bridge-helper, and returns the helper to use.
+pub fn check_bridge(bridge: &str, helper: Option<&str>) -> Result<NetBackend, Error> {
+    if !Path::new("/sys/class/net")
+        .join(bridge)
+        .join("bridge")
+        .is_dir()
+    {
+        return Err(unavailable(format!(
+            "There is no bridge named {}. Create one (e.g. `sudo ip link add {} type bridge` and add the LAN interface to it) or choose another with `config set <name> bridge <bridge>`.",
+            bridge, bridge
+        )));
+    }
+    let helper = match helper {
+        Some(helper) => PathBuf::from(helper),
+        None => BRIDGE_HELPERS
+            .iter()
+            .map(PathBuf::from)
+            .find(|path| path.is_file())
+            .ok_or_else(|| {
+                Error::Io(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    "qemu-bridge-helper was not found. Install it (it ships with QEMU, e.g. the qemu-system-common package) or set its path with `config set <name> bridge-helper <path>`.",
+                ))
+            })?,
+    };
+    let metadata = fs::metadata(&helper).map_err(|err| {
+        Error::Io(std::io::Error::new(
+            err.kind(),
+            format!("{}: {}", helper.display(), err),
+        ))
+    })?;
+
+    if current_uid() != Some(0) {
+        let setuid_root = metadata.mode() & 0o4000 != 0 && metadata.uid() == 0;
+        if !setuid_root && !has_net_admin(&helper) {
+            return Err(unavailable(format!(
+                "{} can only create the tap device when it runs as root. Make it setuid root with `sudo chown root {} && sudo chmod u+s {}`.",
+                helper.display(),
+                helper.display(),
+                helper.display()
+            )));
+        }
+    }
+
+    match read_bridge_acl(Path::new(BRIDGE_CONF)) {
+        Ok(acl) if !acl.allows(bridge) => Err(unavailable(format!(
+            "{} does not allow the bridge {}. Add the line `allow {}` to it.",
+            BRIDGE_CONF, bridge, bridge
+        ))),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Err(unavailable(format!(
+            "{} does not exist, so qemu-bridge-helper refuses every bridge. Create it with the line `allow {}` (e.g. `sudo mkdir -p /etc/qemu && echo 'allow {}' | sudo tee -a {}`).",
+            BRIDGE_CONF, bridge, bridge, BRIDGE_CONF
+        ))),
+        // The helper reads the ACL as root; an unreadable file cannot be checked here.
+        _ => Ok(NetBackend::Bridge {
+            name: bridge.to_string(),
+            helper,
+        }),
+    }
+}
+
+/// Whether the helper has CAP_NET_ADMIN as a file capability, which some
+/// distributions use instead of the setuid bit.
+fn has_net_admin(helper: &Path) -> bool {
+    Command::new("getcap")
+        .arg(helper)
+        .output()
+        .is_ok_and(|output| String::from_utf8_lossy(&output.stdout).contains("cap_net_admin"))
+}
+
+/// The `allow` and `deny` rules of a qemu-bridge-helper ACL.
+#[derive(Debug, Default)]
+struct BridgeAcl {
+    allow: Vec<String>,
+    deny: Vec<String>,
+}
+
+impl BridgeAcl {
+    /// Denials win over permissions, as in qemu-bridge-helper.
+    fn allows(&self, bridge: &str) -> bool {
+        let matches = |rules: &[String]| rules.iter().any(|r| r == "all" || r == bridge);
+        !matches(&self.deny) && matches(&self.allow)
+    }
+
+    /// Adds the rules in `content`; `include` lines name further files.
+    fn parse(&mut self, content: &str) -> Vec<PathBuf> {
+        let mut includes = Vec::new();
+        for line in content.lines() {
+            let line = line.trim();
+            if line.starts_with('#') {
+                continue;
+            }
+            match line.split_once(char::is_whitespace) {
+                Some(("allow", name)) => self.allow.push(name.trim().to_string()),
+                Some(("deny", name)) => self.deny.push(name.trim().to_string()),
+                Some(("include", path)) => includes.push(PathBuf::from(path.trim())),
+                _ => {}
+            }
+        }
+        includes
+    }
+}
+
+fn read_bridge_acl(path: &Path) -> std::io::Result<BridgeAcl> {
+    let mut acl = BridgeAcl::default();
+    let mut pending = vec![path.to_path_buf()];
+    let mut depth = 0;
+    while let Some(file) = pending.pop() {
+        let content = fs::read_to_string(&file)?;
+        pending.extend(acl.parse(&content));
+        depth += 1;
+        if depth > 16 {
+            break;
+        }
+    }
+    Ok(acl)
+}
+
+/// Verifies that the tap interface exists and that the current user may
+/// open it.
+pub fn check_tap(tap: &str) -> Result<NetBackend, Error> {
+    let create = format!(
+        "sudo ip tuntap add dev {} mode tap user $USER && sudo ip link set {} up",
+        tap, tap
+    );
+    let device = Path::new("/sys/class/net").join(tap);
+    if !device.join("tun_flags").exists() {
+        return Err(unavailable(format!(
+            "There is no tap interface named {}. Create it with `{}` and add it to the LAN bridge, or choose another with `config set <name> tap <interface>`.",
+            tap, create
+        )));
+    }
+    let uid = current_uid();
+    if uid != Some(0) {
+        let read_id = |file: &str| {
+            fs::read_to_string(device.join(file))
+                .ok()
+                .and_then(|id| id.trim().parse::<i64>().ok())
+        };
+        // -1 means unset; a tap handed to a group is left to the kernel to check.
+        let owner = read_id("owner").unwrap_or(-1);
+        let group = read_id("group").unwrap_or(-1);
+        if let Some(uid) = uid
+            && owner != uid as i64
+            && (owner != -1 || group == -1)
+        {
+            return Err(unavailable(format!(
+                "The tap interface {} is not owned by you (owner uid {}). Recreate it with `sudo ip tuntap del dev {} mode tap && {}`.",
+                tap, owner, tap, create
+            )));
+        }
+        if let Err(err) = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/net/tun")
+        {
+            return Err(unavailable(format!(
+                "Cannot open /dev/net/tun ({}). Make sure the tun module is loaded and the device is accessible to your user.",
+                err
+            )));
+        }
+    }
+    Ok(NetBackend::Tap {
+        name: tap.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_port_forwards() {
+        let forwards = parse_port_forwards("ssh:tcp:2222:22,adb:tcp:0.0.0.0:5555:5555").unwrap();
+        assert_eq!(forwards.len(), 2);
+        assert_eq!(forwards[0].host_addr, DEFAULT_HOST_ADDR);
+        assert_eq!(forwards[0].hostfwd(), "tcp:127.0.0.1:2222-:22");
+        assert_eq!(forwards[1].hostfwd(), "tcp:0.0.0.0:5555-:5555");
+        assert_eq!(
+            format_port_forwards(&forwards),
+            "ssh:tcp:127.0.0.1:2222:22,adb:tcp:0.0.0.0:5555:5555"
+        );
+        assert!(parse_port_forwards("").unwrap().is_empty());
+
+        let forwards = parse_port_forwards("ssh:tcp:::1:2222:22,adb:tcp:[::]:5555:5555").unwrap();
+        assert_eq!(forwards[0].hostfwd(), "tcp:[::1]:2222-:22");
+        assert_eq!(
+            format_port_forwards(&forwards),
+            "ssh:tcp:[::1]:2222:22,adb:tcp:[::]:5555:5555"
+        );
+    }
+
+    #[test]
+    fn test_parse_port_forwards_errors() {
+        assert!(parse_port_forwards("ssh:tcp:2222").is_err());
+        assert!(parse_port_forwards("ssh:tcp:localhost:2222:22").is_err());
+        assert!(parse_port_forwards("ssh:tcp:[::1:2222:22").is_err());
+        assert!(parse_port_forwards("ssh:icmp:2222:22").is_err());
+        assert!(parse_port_forwards("ssh:tcp:0:22").is_err());
+        assert!(parse_port_forwards("s s:tcp:2222:22").is_err());
+        assert!(parse_port_forwards("ssh:tcp:2222:22,ssh:tcp:2223:22").is_err());
+        assert!(parse_port_forwards("ssh:tcp:2222:22,other:tcp:0.0.0.0:2222:80").is_err());
+        assert!(parse_port_forwards("dns:udp:5353:53,web:tcp:5353:80").is_ok());
+    }
+
+    #[test]
+    fn test_bridge_acl() {
+        let mut acl = BridgeAcl::default();
+        let includes = acl.parse("# lab\nallow br0\ninclude /etc/qemu/lab.conf\n");
+        assert_eq!(includes, [PathBuf::from("/etc/qemu/lab.conf")]);
+        assert!(acl.allows("br0"));
+        assert!(!acl.allows("virbr0"));
+
+        let mut acl = BridgeAcl::default();
+        acl.parse("allow all\ndeny virbr0\n");
+        assert!(acl.allows("br0"));
+        assert!(!acl.allows("virbr0"));
+    }
+
+    #[test]
+    fn test_network_args() {
+        let forwards = parse_port_forwards("ssh:tcp:2222:22").unwrap();
+        assert_eq!(
+            args(&NetBackend::User(forwards), NicModel::E1000),
+            [
+                "-netdev",
+                "user,id=net0,hostfwd=tcp:127.0.0.1:2222-:22",
+                "-device",
+                "e1000,netdev=net0"
+            ]
+        );
+        assert_eq!(
+            args(&NetBackend::None, NicModel::VirtioNet),
+            ["-nic", "none"]
+        );
+        let tap = NetBackend::Tap {
+            name: "tap0".to_string(),
+        };
+        assert_eq!(
+            args(&tap, NicModel::VirtioNet)[1],
+            "tap,id=net0,ifname=tap0,script=no,downscript=no"
+        );
+    }
+}
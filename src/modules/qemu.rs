@@ -1,20 +1,23 @@
+use crate::modules::firmware::{self, Firmware, FirmwareQuery};
 use crate::utils::error::Error;
 use crate::utils::resource::ResourceValue;
-use std::fs;
+use std::fmt;
 use std::io::ErrorKind;
-use std::path::PathBuf;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
 
 pub struct QemuConfig {
     pub binary: String,
-    pub ovmf_code_path: PathBuf,
-    pub ovmf_vars_template: PathBuf,
+    pub arch: &'static str,
+    pub machine: &'static str,
 }
 
 pub fn detect_arch() -> Result<QemuConfig, Error> {
     let arch = std::env::consts::ARCH;
-    let (qemu_binary, ovmf_dir_name) = match arch {
-        "x86_64" => ("qemu-system-x86_64", "OVMF"),
-        "aarch64" => ("qemu-system-aarch64", "AAVMF"),
+    let (qemu_binary, machine) = match arch {
+        "x86_64" => ("qemu-system-x86_64", "pc-i440fx"),
+        "aarch64" => ("qemu-system-aarch64", "virt"),
         _ => {
             return Err(Error::Io(std::io::Error::other(format!(
                 "Unsupported architecture: {}",
@@ -23,42 +26,577 @@ pub fn detect_arch() -> Result<QemuConfig, Error> {
         }
     };
 
-    let ovmf_dir = PathBuf::from(format!("/usr/share/{}", ovmf_dir_name));
-    if !ovmf_dir.exists() {
-        return Err(Error::Io(std::io::Error::new(
-            ErrorKind::NotFound,
-            format!("OVMF/AAVMF directory not found: {}", ovmf_dir.display()),
-        )));
-    }
-
-    let ovmf_code_path = find_ovmf_file(&ovmf_dir, "CODE")?;
-    let ovmf_vars_template = find_ovmf_file(&ovmf_dir, "VARS")?;
-
     Ok(QemuConfig {
         binary: qemu_binary.to_string(),
-        ovmf_code_path,
-        ovmf_vars_template,
+        arch,
+        machine,
     })
 }
 
-fn find_ovmf_file(ovmf_dir: &PathBuf, file_type: &str) -> Result<PathBuf, Error> {
-    for entry in fs::read_dir(ovmf_dir)? {
-        let entry = entry?;
-        let path = entry.path();
-        if let Some(file_name) = path.file_name().and_then(|s| s.to_str()) {
-            if file_name.contains(file_type) && file_name.ends_with(".fd") {
-                return Ok(path);
+impl QemuConfig {
+    /// Describes the firmware a newly selected pair has to satisfy.
+    pub fn firmware_query(&self, secure_boot: bool) -> FirmwareQuery<'static> {
+        if secure_boot {
+            FirmwareQuery {
+                arch: self.arch,
+                machine: if self.arch == "x86_64" {
+                    "pc-q35"
+                } else {
+                    self.machine
+                },
+                features: &["secure-boot", "enrolled-keys"],
+                excluded_features: &[],
+            }
+        } else {
+            FirmwareQuery {
+                arch: self.arch,
+                machine: self.machine,
+                features: &[],
+                excluded_features: &["requires-smm", "enrolled-keys"],
+            }
+        }
+    }
+
+    /// Selects the installed firmware best suited for a new machine.
+    pub fn find_firmware(&self, secure_boot: bool) -> Result<Firmware, Error> {
+        firmware::find(&self.firmware_query(secure_boot)).map_err(|err| {
+            if secure_boot {
+                Error::Io(std::io::Error::new(
+                    ErrorKind::NotFound,
+                    "Secure Boot requires a Secure Boot capable firmware build with a vars template that has Microsoft keys enrolled (e.g. OVMF_CODE_4M.secboot.fd with OVMF_VARS_4M.ms.fd), but none is installed. Install your distribution's OVMF/edk2 package or pass --ovmf-code/--ovmf-vars.",
+                ))
+            } else {
+                err
             }
+        })
+    }
+
+    /// Names listed by `<binary> <option> help`, e.g. the display backends
+    /// for `-display`. `None` when QEMU could not be asked.
+    pub fn help_list(&self, option: &str) -> Option<Vec<String>> {
+        let output = Command::new(&self.binary)
+            .args([option, "help"])
+            .stderr(Stdio::null())
+            .output()
+            .ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        parse_help_list(&String::from_utf8_lossy(&output.stdout))
+    }
+
+    /// Fails when the QEMU binary was built without `display`.
+    pub fn check_display(&self, display: DisplayBackend) -> Result<(), Error> {
+        let Some(name) = display.help_name() else {
+            return Ok(());
+        };
+        match self.help_list("-display") {
+            Some(available) if !available.iter().any(|a| a == name) => {
+                Err(Error::Io(std::io::Error::new(
+                    ErrorKind::Unsupported,
+                    format!(
+                        "{} does not support the {} display (available: {}). Choose another one with `run --display` or `config set <name> display <backend>`.",
+                        self.binary,
+                        name,
+                        available.join(", ")
+                    ),
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether the QEMU binary has the device `name` (with the property
+    /// `prop`), checked with `-device <name>,help`.
+    pub fn has_device(&self, name: &str, prop: Option<&str>) -> bool {
+        let Ok(output) = Command::new(&self.binary)
+            .args(["-device", &format!("{},help", name)])
+            .stderr(Stdio::null())
+            .output()
+        else {
+            return false;
+        };
+        output.status.success()
+            && prop.is_none_or(|prop| String::from_utf8_lossy(&output.stdout).contains(prop))
+    }
+
+    /// Fails when the QEMU binary lacks the device `gpu` needs.
+    pub fn check_gpu(&self, gpu: Gpu) -> Result<(), Error> {
+        let (device, prop) = match gpu {
+            Gpu::Rutabaga => ("virtio-vga-rutabaga", None),
+            Gpu::Venus => ("virtio-vga-gl", Some("venus")),
+            _ => return Ok(()),
+        };
+        if self.has_device(device, prop) {
+            return Ok(());
+        }
+        Err(Error::Io(std::io::Error::new(
+            ErrorKind::Unsupported,
+            format!(
+                "{} has no {}{}, which the {} GPU needs. Choose another one with `config set <name> gpu virtio-gl`.",
+                self.binary,
+                device,
+                prop.map(|p| format!(" with {} support", p))
+                    .unwrap_or_default(),
+                gpu
+            ),
+        )))
+    }
+
+    /// Fails when the QEMU binary was built without the audio `backend`.
+    pub fn check_audio(&self, backend: AudioBackend) -> Result<(), Error> {
+        match self.help_list("-audiodev") {
+            Some(available) if !available.iter().any(|a| a == backend.driver()) => {
+                Err(Error::Io(std::io::Error::new(
+                    ErrorKind::Unsupported,
+                    format!(
+                        "{} does not support the {} audio backend (available drivers: {}). Choose another one with `config set <name> audio <backend>`.",
+                        self.binary,
+                        backend,
+                        available.join(", ")
+                    ),
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Parses the list QEMU prints for `<option> help`: a line such as
+/// `Available display backend types:` followed by one name per line.
+fn parse_help_list(output: &str) -> Option<Vec<String>> {
+    let mut lines = output.lines();
+    lines.find(|line| line.starts_with("Available") && line.trim_end().ends_with(':'))?;
+    Some(
+        lines
+            .map(str::trim)
+            .take_while(|line| !line.is_empty())
+            .filter_map(|line| line.split_whitespace().next())
+            .map(str::to_string)
+            .collect(),
+    )
+}
+
+/// Where the guest's screen is shown.
+#[derive(Debug, Clone, Copy, PartialEq, Default, clap::ValueEnum)]
+pub enum DisplayBackend {
+    /// SDL window
+    #[default]
+    Sdl,
+    /// GTK window
+    Gtk,
+    /// SPICE client window started by QEMU
+    SpiceApp,
+    /// VNC server, see the VNC_DISPLAY setting
+    Vnc,
+    /// no window; OpenGL rendering for virtio-gpu
+    EglHeadless,
+    /// no display at all
+    None,
+}
+
+impl FromStr for DisplayBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "sdl" => Ok(DisplayBackend::Sdl),
+            "gtk" => Ok(DisplayBackend::Gtk),
+            "spice-app" => Ok(DisplayBackend::SpiceApp),
+            "vnc" => Ok(DisplayBackend::Vnc),
+            "egl-headless" => Ok(DisplayBackend::EglHeadless),
+            "none" => Ok(DisplayBackend::None),
+            _ => Err(format!(
+                "Unknown display: {} (expected sdl, gtk, spice-app, vnc, egl-headless or none)",
+                s
+            )),
         }
     }
-    Err(Error::Io(std::io::Error::new(
-        ErrorKind::NotFound,
-        format!(
-            "OVMF/AAVMF {} file not found in {}",
-            file_type,
-            ovmf_dir.display()
-        ),
-    )))
+}
+
+impl fmt::Display for DisplayBackend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DisplayBackend::Sdl => write!(f, "sdl"),
+            DisplayBackend::Gtk => write!(f, "gtk"),
+            DisplayBackend::SpiceApp => write!(f, "spice-app"),
+            DisplayBackend::Vnc => write!(f, "vnc"),
+            DisplayBackend::EglHeadless => write!(f, "egl-headless"),
+            DisplayBackend::None => write!(f, "none"),
+        }
+    }
+}
+
+impl DisplayBackend {
+    /// The name in `-display help`; VNC and `none` are not listed there.
+    fn help_name(&self) -> Option<&'static str> {
+        match self {
+            DisplayBackend::Sdl => Some("sdl"),
+            DisplayBackend::Gtk => Some("gtk"),
+            DisplayBackend::SpiceApp => Some("spice-app"),
+            DisplayBackend::EglHeadless => Some("egl-headless"),
+            DisplayBackend::Vnc | DisplayBackend::None => None,
+        }
+    }
+
+    /// Whether the display can show an OpenGL-accelerated virtio-gpu, given
+    /// the `DISPLAY_GL` setting.
+    pub fn has_gl(&self, gl: bool) -> bool {
+        match self {
+            DisplayBackend::Sdl | DisplayBackend::Gtk | DisplayBackend::SpiceApp => gl,
+            DisplayBackend::EglHeadless => true,
+            DisplayBackend::Vnc | DisplayBackend::None => false,
+        }
+    }
+
+    /// QEMU arguments for the display. `vnc_display` is VNC's
+    /// `host:display` listen address; `full_screen` applies to the sdl and
+    /// gtk windows.
+    pub fn args(&self, gl: bool, full_screen: bool, vnc_display: &str) -> Vec<String> {
+        let gl = if gl { "on" } else { "off" };
+        let full_screen = if full_screen { ",full-screen=on" } else { "" };
+        let display = match self {
+            DisplayBackend::Sdl => format!("sdl,show-cursor=on,gl={}{}", gl, full_screen),
+            DisplayBackend::Gtk => format!("gtk,show-cursor=on,gl={}{}", gl, full_screen),
+            DisplayBackend::SpiceApp => format!("spice-app,gl={}", gl),
+            DisplayBackend::Vnc => format!("vnc={}", vnc_display),
+            DisplayBackend::EglHeadless => "egl-headless".to_string(),
+            DisplayBackend::None => "none".to_string(),
+        };
+        vec!["-display".to_string(), display]
+    }
+}
+
+/// The emulated graphics card.
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Gpu {
+    /// standard VGA, no acceleration
+    Std,
+    /// virtio-vga without OpenGL
+    Virtio,
+    /// virtio-vga with OpenGL (virgl); needs an OpenGL display
+    VirtioGl,
+    /// virtio-vga-rutabaga with gfxstream Vulkan; needs an OpenGL display
+    #[value(alias = "virtio-gpu-rutabaga")]
+    Rutabaga,
+    /// virtio-vga-gl with Venus Vulkan; needs an OpenGL display
+    Venus,
+    /// no graphics card
+    None,
+}
+
+impl FromStr for Gpu {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "std" => Ok(Gpu::Std),
+            "virtio" => Ok(Gpu::Virtio),
+            "virtio-gl" => Ok(Gpu::VirtioGl),
+            "rutabaga" | "virtio-gpu-rutabaga" => Ok(Gpu::Rutabaga),
+            "venus" => Ok(Gpu::Venus),
+            "none" => Ok(Gpu::None),
+            _ => Err(format!(
+                "Unknown GPU: {} (expected std, virtio, virtio-gl, rutabaga, venus or none)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Gpu {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Gpu::Std => write!(f, "std"),
+            Gpu::Virtio => write!(f, "virtio"),
+            Gpu::VirtioGl => write!(f, "virtio-gl"),
+            Gpu::Rutabaga => write!(f, "rutabaga"),
+            Gpu::Venus => write!(f, "venus"),
+            Gpu::None => write!(f, "none"),
+        }
+    }
+}
+
+impl Gpu {
+    /// The GPU used when none is configured: the best one the display can
+    /// show.
+    pub fn default_for(display_gl: bool) -> Self {
+        if display_gl {
+            Gpu::VirtioGl
+        } else {
+            Gpu::Virtio
+        }
+    }
+
+    pub fn needs_gl(&self) -> bool {
+        matches!(self, Gpu::VirtioGl | Gpu::Rutabaga | Gpu::Venus)
+    }
+
+    /// QEMU arguments for the card. `memory` is the guest memory size, which
+    /// also sizes the host-visible blob region of Rutabaga and Venus, and
+    /// which Venus has to share with the host.
+    pub fn args(&self, (xres, yres): (u32, u32), max_outputs: &str, memory: &str) -> Vec<String> {
+        let heads = format!("xres={},yres={},max_outputs={}", xres, yres, max_outputs);
+        let device = match self {
+            Gpu::Std => format!("VGA,xres={},yres={}", xres, yres),
+            Gpu::Virtio => format!("virtio-vga,{}", heads),
+            Gpu::VirtioGl => format!("virtio-vga-gl,{}", heads),
+            Gpu::Rutabaga => format!(
+                "virtio-vga-rutabaga,gfxstream-vulkan=on,cross-domain=on,hostmem={},{}",
+                memory, heads
+            ),
+            Gpu::Venus => format!(
+                "virtio-vga-gl,blob=true,hostmem={},venus=true,{}",
+                memory, heads
+            ),
+            Gpu::None => return vec!["-vga".to_string(), "none".to_string()],
+        };
+        let mut args = vec!["-device".to_string(), device];
+        if *self == Gpu::Venus {
+            // Venus maps guest memory into the host renderer, which needs it shared.
+            args.extend([
+                "-object".to_string(),
+                format!("memory-backend-memfd,id=gpu-mem,size={},share=on", memory),
+                "-machine".to_string(),
+                "memory-backend=gpu-mem".to_string(),
+            ]);
+        }
+        args
+    }
+}
+
+/// Where the guest's sound goes on the host.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum AudioBackend {
+    Pipewire,
+    Pulseaudio,
+    Alsa,
+    #[default]
+    Sdl,
+    /// recorded to a WAV file
+    Wav,
+    /// the guest has a sound card but nothing is played
+    None,
+}
+
+impl FromStr for AudioBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "pipewire" => Ok(AudioBackend::Pipewire),
+            "pulseaudio" | "pa" => Ok(AudioBackend::Pulseaudio),
+            "alsa" => Ok(AudioBackend::Alsa),
+            "sdl" => Ok(AudioBackend::Sdl),
+            "wav" => Ok(AudioBackend::Wav),
+            "none" => Ok(AudioBackend::None),
+            _ => Err(format!(
+                "Unknown audio backend: {} (expected pipewire, pulseaudio, alsa, sdl, wav or none)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for AudioBackend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AudioBackend::Pipewire => write!(f, "pipewire"),
+            AudioBackend::Pulseaudio => write!(f, "pulseaudio"),
+            AudioBackend::Alsa => write!(f, "alsa"),
+            AudioBackend::Sdl => write!(f, "sdl"),
+            AudioBackend::Wav => write!(f, "wav"),
+            AudioBackend::None => write!(f, "none"),
+        }
+    }
+}
+
+impl AudioBackend {
+    /// The driver name used by `-audiodev` and listed in `-audiodev help`.
+    fn driver(&self) -> &'static str {
+        match self {
+            AudioBackend::Pipewire => "pipewire",
+            AudioBackend::Pulseaudio => "pa",
+            AudioBackend::Alsa => "alsa",
+            AudioBackend::Sdl => "sdl",
+            AudioBackend::Wav => "wav",
+            AudioBackend::None => "none",
+        }
+    }
+
+    /// The `-audiodev` arguments defining `audio0`; `wav_path` is the file
+    /// the wav backend writes.
+    pub fn args(&self, wav_path: &Path) -> Vec<String> {
+        let mut audiodev = format!("{},id=audio0", self.driver());
+        if *self == AudioBackend::Wav {
+            audiodev.push_str(&format!(",path={}", wav_path.display()));
+        }
+        vec!["-audiodev".to_string(), audiodev]
+    }
+}
+
+/// The sound card the guest sees.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum AudioDevice {
+    /// Intel HD Audio with an output, or a duplex codec when input is enabled
+    #[default]
+    IntelHda,
+    /// AC'97, which always has a line in
+    Ac97,
+    /// virtio-sound with an output stream and optionally an input stream
+    VirtioSound,
+}
+
+impl FromStr for AudioDevice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "intel-hda" => Ok(AudioDevice::IntelHda),
+            "ac97" => Ok(AudioDevice::Ac97),
+            "virtio-sound" => Ok(AudioDevice::VirtioSound),
+            _ => Err(format!(
+                "Unknown audio device: {} (expected intel-hda, ac97 or virtio-sound)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for AudioDevice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AudioDevice::IntelHda => write!(f, "intel-hda"),
+            AudioDevice::Ac97 => write!(f, "ac97"),
+            AudioDevice::VirtioSound => write!(f, "virtio-sound"),
+        }
+    }
+}
+
+impl AudioDevice {
+    /// QEMU arguments adding the card on `audio0`; `input` gives the guest a
+    /// microphone where the card allows choosing.
+    pub fn args(&self, input: bool) -> Vec<String> {
+        let devices = match self {
+            AudioDevice::IntelHda => vec![
+                "intel-hda".to_string(),
+                format!(
+                    "{},audiodev=audio0",
+                    if input { "hda-duplex" } else { "hda-output" }
+                ),
+            ],
+            AudioDevice::Ac97 => vec!["AC97,audiodev=audio0".to_string()],
+            AudioDevice::VirtioSound => vec![format!(
+                "virtio-sound-pci,audiodev=audio0,streams={}",
+                if input { 2 } else { 1 }
+            )],
+        };
+        devices
+            .into_iter()
+            .flat_map(|device| ["-device".to_string(), device])
+            .collect()
+    }
+}
+
+/// How install and recovery images are presented to the guest. ChromeOS
+/// recovery only offers to install from removable USB devices, hence the
+/// default.
+#[derive(Debug, Clone, Copy, PartialEq, Default, clap::ValueEnum)]
+pub enum MediaAttach {
+    /// usb-storage on an xhci controller
+    #[default]
+    Usb,
+    /// IDE CD-ROM drive
+    IdeCd,
+    /// virtio-blk disk
+    Virtio,
+}
+
+impl FromStr for MediaAttach {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "usb" => Ok(MediaAttach::Usb),
+            "ide-cd" => Ok(MediaAttach::IdeCd),
+            "virtio" => Ok(MediaAttach::Virtio),
+            _ => Err(format!(
+                "Unknown media attach mode: {} (expected usb, ide-cd or virtio)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for MediaAttach {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MediaAttach::Usb => write!(f, "usb"),
+            MediaAttach::IdeCd => write!(f, "ide-cd"),
+            MediaAttach::Virtio => write!(f, "virtio"),
+        }
+    }
+}
+
+impl MediaAttach {
+    /// QEMU arguments attaching `path` as drive `id`. USB media expect an
+    /// xhci controller named `xhci`, see [`MediaAttach::controller_args`].
+    pub fn drive_args(&self, id: &str, path: &str) -> Vec<String> {
+        let (drive, device) = match self {
+            MediaAttach::Usb => (
+                format!("if=none,id={},format=raw,file={}", id, path),
+                format!("usb-storage,bus=xhci.0,drive={},removable=on", id),
+            ),
+            MediaAttach::IdeCd => (
+                format!(
+                    "if=none,id={},format=raw,media=cdrom,readonly=on,file={}",
+                    id, path
+                ),
+                format!("ide-cd,drive={}", id),
+            ),
+            MediaAttach::Virtio => (
+                format!("if=none,id={},format=raw,file={}", id, path),
+                format!("virtio-blk-pci,drive={}", id),
+            ),
+        };
+        vec!["-drive".to_string(), drive, "-device".to_string(), device]
+    }
+
+    pub fn controller_args(&self) -> Vec<String> {
+        match self {
+            MediaAttach::Usb => vec!["-device".to_string(), "qemu-xhci,id=xhci".to_string()],
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Creates a disk image with `qemu-img`.
+pub fn create_disk(path: &Path, format: &str, size: u64) -> Result<(), Error> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let status = Command::new("qemu-img")
+        .arg("create")
+        .arg("-f")
+        .arg(format)
+        .arg(path)
+        .arg(size.to_string())
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .status()
+        .map_err(|err| {
+            Error::Io(std::io::Error::new(
+                err.kind(),
+                format!("Failed to run qemu-img (is it installed?): {}", err),
+            ))
+        })?;
+    if !status.success() {
+        return Err(Error::Io(std::io::Error::other(format!(
+            "Failed to create disk image: {}",
+            path.display()
+        ))));
+    }
+    Ok(())
 }
 
 pub fn resolve_value(value: &ResourceValue, total: u64, unit: Option<&str>) -> String {
@@ -84,3 +622,99 @@ pub fn resolve_value(value: &ResourceValue, total: u64, unit: Option<&str>) -> S
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_help_list() {
+        let output = "Available display backend types:\nnone\ngtk\nsdl\negl-headless\ncurses\nspice-app\ndbus\n";
+        assert_eq!(
+            parse_help_list(output).unwrap(),
+            [
+                "none",
+                "gtk",
+                "sdl",
+                "egl-headless",
+                "curses",
+                "spice-app",
+                "dbus"
+            ]
+        );
+        let output = "Available audio drivers:\nnone\nalsa\npa\n\n";
+        assert_eq!(parse_help_list(output).unwrap(), ["none", "alsa", "pa"]);
+        assert!(parse_help_list("qemu: unknown option\n").is_none());
+    }
+
+    #[test]
+    fn test_display_args() {
+        assert_eq!(
+            DisplayBackend::Sdl.args(true, false, "127.0.0.1:0"),
+            ["-display", "sdl,show-cursor=on,gl=on"]
+        );
+        assert_eq!(
+            DisplayBackend::Gtk.args(false, true, "127.0.0.1:0"),
+            ["-display", "gtk,show-cursor=on,gl=off,full-screen=on"]
+        );
+        assert_eq!(
+            DisplayBackend::Vnc.args(true, true, "127.0.0.1:3"),
+            ["-display", "vnc=127.0.0.1:3"]
+        );
+        assert!(!DisplayBackend::Vnc.has_gl(true));
+        assert!(!DisplayBackend::Gtk.has_gl(false));
+        assert!(DisplayBackend::EglHeadless.has_gl(false));
+    }
+
+    #[test]
+    fn test_audio_args() {
+        let wav = Path::new("/tmp/vm.wav");
+        assert_eq!(
+            AudioBackend::Pulseaudio.args(wav),
+            ["-audiodev", "pa,id=audio0"]
+        );
+        assert_eq!(
+            AudioBackend::Wav.args(wav),
+            ["-audiodev", "wav,id=audio0,path=/tmp/vm.wav"]
+        );
+        assert_eq!(
+            AudioDevice::IntelHda.args(false),
+            [
+                "-device",
+                "intel-hda",
+                "-device",
+                "hda-output,audiodev=audio0"
+            ]
+        );
+        assert_eq!(
+            AudioDevice::IntelHda.args(true)[3],
+            "hda-duplex,audiodev=audio0"
+        );
+        assert_eq!(
+            AudioDevice::VirtioSound.args(true),
+            ["-device", "virtio-sound-pci,audiodev=audio0,streams=2"]
+        );
+    }
+
+    #[test]
+    fn test_gpu_args() {
+        assert_eq!(
+            Gpu::Std.args((1920, 1080), "2", "4G"),
+            ["-device", "VGA,xres=1920,yres=1080"]
+        );
+        assert_eq!(
+            Gpu::VirtioGl.args((1280, 800), "1", "4G"),
+            ["-device", "virtio-vga-gl,xres=1280,yres=800,max_outputs=1"]
+        );
+        let venus = Gpu::Venus.args((1280, 800), "1", "8G");
+        assert_eq!(
+            venus[1],
+            "virtio-vga-gl,blob=true,hostmem=8G,venus=true,xres=1280,yres=800,max_outputs=1"
+        );
+        assert_eq!(venus[3], "memory-backend-memfd,id=gpu-mem,size=8G,share=on");
+        assert_eq!(Gpu::None.args((1280, 800), "1", "4G"), ["-vga", "none"]);
+        assert_eq!(Gpu::default_for(false), Gpu::Virtio);
+        assert_eq!(Gpu::from_str("virtio-gl"), Ok(Gpu::VirtioGl));
+        assert_eq!(Gpu::from_str("virtio-gpu-rutabaga"), Ok(Gpu::Rutabaga));
+    }
+}
@@ -0,0 +1,545 @@
+const FV_SIGNATURE: &[u8; 4] = b"_FVH";
+const VARIABLE_DATA: u16 = 0x55AA;
+const VAR_ADDED: u8 = 0x3F;
+const VAR_IN_DELETED_TRANSITION: u8 = 0xFE;
+const STORE_HEADER_SIZE: usize = 28;
+const AUTH_HEADER_SIZE: usize = 60;
+const PLAIN_HEADER_SIZE: usize = 32;
+
+pub type Guid = [u8; 16];
+
+/// Encodes a GUID in the mixed-endian layout used by UEFI.
+pub const fn guid(d1: u32, d2: u16, d3: u16, d4: [u8; 8]) -> Guid {
+    let a = d1.to_le_bytes();
+    let b = d2.to_le_bytes();
+    let c = d3.to_le_bytes();
+    [
+        a[0], a[1], a[2], a[3], b[0], b[1], c[0], c[1], d4[0], d4[1], d4[2], d4[3], d4[4], d4[5],
+        d4[6], d4[7],
+    ]
+}
+
+pub const EFI_GLOBAL_VARIABLE: Guid = guid(
+    0x8BE4DF61,
+    0x93CA,
+    0x11D2,
+    [0xAA, 0x0D, 0x00, 0xE0, 0x98, 0x03, 0x2B, 0x8C],
+);
+const AUTHENTICATED_VARIABLE_GUID: Guid = guid(
+    0xAAF32C78,
+    0x947B,
+    0x439A,
+    [0xA1, 0x80, 0x2E, 0x14, 0x4E, 0xC3, 0x77, 0x92],
+);
+const VARIABLE_GUID: Guid = guid(
+    0xDDCF3616,
+    0x3275,
+    0x4164,
+    [0x98, 0xB6, 0xFE, 0x85, 0x70, 0x7F, 0xFE, 0x7D],
+);
+
+/// Attributes used for `Boot####` and `BootOrder`: non-volatile, boot service
+/// and runtime access.
+pub const BOOT_VARIABLE_ATTRIBUTES: u32 = 0x7;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variable {
+    pub name: String,
+    pub vendor: Guid,
+    pub attributes: u32,
+    pub data: Vec<u8>,
+    monotonic_count: u64,
+    timestamp: [u8; 16],
+    pubkey_index: u32,
+}
+
+/// The UEFI variable store inside an OVMF/AAVMF `*_VARS.fd` image.
+///
+/// The firmware never edits variables in place: updates append a new copy and
+/// flip the state bits of the old one. Writing back does what the firmware's
+/// reclaim does instead and lays out only the live variables, so offline edits
+/// never run out of space.
+pub struct VarStore {
+    image: Vec<u8>,
+    store_offset: usize,
+    store_size: usize,
+    authenticated: bool,
+    pub variables: Vec<Variable>,
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        data.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+fn align4(value: usize) -> usize {
+    (value + 3) & !3
+}
+
+fn decode_name(bytes: &[u8]) -> String {
+    let units: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .take_while(|&u| u != 0)
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+fn encode_name(name: &str) -> Vec<u8> {
+    name.encode_utf16()
+        .chain(std::iter::once(0))
+        .flat_map(u16::to_le_bytes)
+        .collect()
+}
+
+impl VarStore {
+    /// Parses a complete `*_VARS.fd` image.
+    pub fn parse(image: Vec<u8>) -> Result<Self, String> {
+        if image.get(0x28..0x2C) != Some(FV_SIGNATURE.as_slice()) {
+            return Err("Not a UEFI firmware volume (missing _FVH signature)".to_string());
+        }
+        let store_offset =
+            read_u16(&image, 0x30).ok_or("Truncated firmware volume header")? as usize;
+        let signature: Guid = image
+            .get(store_offset..store_offset + 16)
+            .and_then(|s| s.try_into().ok())
+            .ok_or("Truncated variable store header")?;
+        let authenticated = match signature {
+            AUTHENTICATED_VARIABLE_GUID => true,
+            VARIABLE_GUID => false,
+            _ => return Err("Unknown variable store format".to_string()),
+        };
+        let store_size =
+            read_u32(&image, store_offset + 16).ok_or("Truncated variable store header")? as usize;
+        if store_offset + store_size > image.len() || store_size < STORE_HEADER_SIZE {
+            return Err("Variable store size exceeds the image".to_string());
+        }
+
+        let end = store_offset + store_size;
+        let header_size = if authenticated {
+            AUTH_HEADER_SIZE
+        } else {
+            PLAIN_HEADER_SIZE
+        };
+        let mut found: Vec<(u8, Variable)> = Vec::new();
+        let mut offset = align4(store_offset + STORE_HEADER_SIZE);
+        while offset + header_size <= end && read_u16(&image, offset) == Some(VARIABLE_DATA) {
+            let state = image[offset + 2];
+            let attributes = read_u32(&image, offset + 4).ok_or("Truncated variable")?;
+            let (monotonic_count, timestamp, pubkey_index, sizes_at) = if authenticated {
+                (
+                    read_u64(&image, offset + 8).ok_or("Truncated variable")?,
+                    image[offset + 16..offset + 32].try_into().unwrap(),
+                    read_u32(&image, offset + 32).ok_or("Truncated variable")?,
+                    offset + 36,
+                )
+            } else {
+                (0, [0; 16], 0, offset + 8)
+            };
+            let name_size = read_u32(&image, sizes_at).ok_or("Truncated variable")? as usize;
+            let data_size = read_u32(&image, sizes_at + 4).ok_or("Truncated variable")? as usize;
+            let vendor: Guid = image[sizes_at + 8..sizes_at + 24].try_into().unwrap();
+            let name_at = offset + header_size;
+            let data_at = name_at + name_size;
+            if data_at + data_size > end {
+                return Err(format!(
+                    "Variable at offset {:#x} overruns the store",
+                    offset
+                ));
+            }
+
+            if state == VAR_ADDED || state == (VAR_ADDED & VAR_IN_DELETED_TRANSITION) {
+                found.push((
+                    state,
+                    Variable {
+                        name: decode_name(&image[name_at..data_at]),
+                        vendor,
+                        attributes,
+                        data: image[data_at..data_at + data_size].to_vec(),
+                        monotonic_count,
+                        timestamp,
+                        pubkey_index,
+                    },
+                ));
+            }
+            offset = align4(data_at + data_size);
+        }
+
+        // A copy caught in the middle of an update is only live if the update
+        // never completed.
+        let variables = found
+            .iter()
+            .filter(|(state, var)| {
+                *state == VAR_ADDED
+                    || !found.iter().any(|(other_state, other)| {
+                        *other_state == VAR_ADDED
+                            && other.name == var.name
+                            && other.vendor == var.vendor
+                    })
+            })
+            .map(|(_, var)| var.clone())
+            .collect();
+
+        Ok(Self {
+            image,
+            store_offset,
+            store_size,
+            authenticated,
+            variables,
+        })
+    }
+
+    pub fn get(&self, vendor: &Guid, name: &str) -> Option<&Variable> {
+        self.variables
+            .iter()
+            .find(|v| &v.vendor == vendor && v.name == name)
+    }
+
+    /// Creates or replaces a variable.
+    pub fn set(&mut self, vendor: &Guid, name: &str, attributes: u32, data: Vec<u8>) {
+        match self
+            .variables
+            .iter_mut()
+            .find(|v| &v.vendor == vendor && v.name == name)
+        {
+            Some(var) => {
+                var.attributes = attributes;
+                var.data = data;
+            }
+            None => self.variables.push(Variable {
+                name: name.to_string(),
+                vendor: *vendor,
+                attributes,
+                data,
+                monotonic_count: 0,
+                timestamp: [0; 16],
+                pubkey_index: 0,
+            }),
+        }
+    }
+
+    /// Removes a variable, returning whether it existed.
+    pub fn remove(&mut self, vendor: &Guid, name: &str) -> bool {
+        let before = self.variables.len();
+        self.variables
+            .retain(|v| !(&v.vendor == vendor && v.name == name));
+        before != self.variables.len()
+    }
+
+    /// Produces the image with the variable store rewritten to hold exactly
+    /// the current variables. Everything outside the store is left untouched.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        let mut image = self.image.clone();
+        let end = self.store_offset + self.store_size;
+        let start = align4(self.store_offset + STORE_HEADER_SIZE);
+        image[self.store_offset + STORE_HEADER_SIZE..end].fill(0xFF);
+
+        let mut offset = start;
+        for var in &self.variables {
+            let name = encode_name(&var.name);
+            let mut record = Vec::new();
+            record.extend_from_slice(&VARIABLE_DATA.to_le_bytes());
+            record.push(VAR_ADDED);
+            record.push(0);
+            record.extend_from_slice(&var.attributes.to_le_bytes());
+            if self.authenticated {
+                record.extend_from_slice(&var.monotonic_count.to_le_bytes());
+                record.extend_from_slice(&var.timestamp);
+                record.extend_from_slice(&var.pubkey_index.to_le_bytes());
+            }
+            record.extend_from_slice(&(name.len() as u32).to_le_bytes());
+            record.extend_from_slice(&(var.data.len() as u32).to_le_bytes());
+            record.extend_from_slice(&var.vendor);
+            record.extend_from_slice(&name);
+            record.extend_from_slice(&var.data);
+
+            if offset + record.len() > end {
+                return Err("The variable store is full".to_string());
+            }
+            image[offset..offset + record.len()].copy_from_slice(&record);
+            offset = align4(offset + record.len());
+        }
+        Ok(image)
+    }
+}
+
+/// Parsed `EFI_LOAD_OPTION` stored in a `Boot####` variable.
+pub struct LoadOption {
+    pub attributes: u32,
+    pub description: String,
+}
+
+impl LoadOption {
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let attributes = read_u32(data, 0)?;
+        let description = decode_name(data.get(6..)?);
+        Some(Self {
+            attributes,
+            description,
+        })
+    }
+
+    /// `LOAD_OPTION_ACTIVE`
+    pub fn active(&self) -> bool {
+        self.attributes & 1 != 0
+    }
+}
+
+pub fn boot_variable_name(id: u16) -> String {
+    format!("Boot{:04X}", id)
+}
+
+/// Parses a boot entry number such as `0003`, `3` or `Boot0003`.
+pub fn parse_boot_id(value: &str) -> Result<u16, String> {
+    let digits = value.strip_prefix("Boot").unwrap_or(value);
+    u16::from_str_radix(digits, 16).map_err(|_| format!("Invalid boot entry: {}", value))
+}
+
+impl VarStore {
+    pub fn boot_order(&self) -> Vec<u16> {
+        self.get(&EFI_GLOBAL_VARIABLE, "BootOrder")
+            .map(|var| {
+                var.data
+                    .chunks_exact(2)
+                    .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn set_boot_order(&mut self, order: &[u16]) {
+        let attributes = self
+            .get(&EFI_GLOBAL_VARIABLE, "BootOrder")
+            .map_or(BOOT_VARIABLE_ATTRIBUTES, |var| var.attributes);
+        let data = order.iter().flat_map(|id| id.to_le_bytes()).collect();
+        self.set(&EFI_GLOBAL_VARIABLE, "BootOrder", attributes, data);
+    }
+
+    /// All `Boot####` entries, sorted by number.
+    pub fn boot_entries(&self) -> Vec<(u16, LoadOption)> {
+        let mut entries: Vec<(u16, LoadOption)> = self
+            .variables
+            .iter()
+            .filter(|var| var.vendor == EFI_GLOBAL_VARIABLE)
+            .filter_map(|var| {
+                let digits = var.name.strip_prefix("Boot")?;
+                if digits.len() != 4 {
+                    return None;
+                }
+                let id = u16::from_str_radix(digits, 16).ok()?;
+                Some((id, LoadOption::parse(&var.data)?))
+            })
+            .collect();
+        entries.sort_by_key(|(id, _)| *id);
+        entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STORE_SIZE: usize = 0x1000;
+
+    /// Builds an empty image laid out like OVMF_VARS.fd: a firmware volume
+    /// header with a block map followed by an authenticated variable store.
+    fn template() -> Vec<u8> {
+        let header_len = 0x48usize;
+        let mut image = vec![0xFF; header_len + STORE_SIZE + 0x1000];
+        image[..0x10].fill(0);
+        image[0x10..0x20].copy_from_slice(&guid(
+            0xFFF12B8D,
+            0x7696,
+            0x4C8B,
+            [0xA9, 0x85, 0x27, 0x47, 0x07, 0x5B, 0x4F, 0x50],
+        ));
+        let len = image.len();
+        image[0x20..0x28].copy_from_slice(&(len as u64).to_le_bytes());
+        image[0x28..0x2C].copy_from_slice(FV_SIGNATURE);
+        image[0x2C..0x30].copy_from_slice(&0x4FEFFu32.to_le_bytes());
+        image[0x30..0x32].copy_from_slice(&(header_len as u16).to_le_bytes());
+        image[0x32..0x38].fill(0);
+        image[0x37] = 2;
+        image[0x38..0x3C].copy_from_slice(&((len / 0x1000) as u32).to_le_bytes());
+        image[0x3C..0x40].copy_from_slice(&0x1000u32.to_le_bytes());
+        image[0x40..0x48].fill(0);
+
+        let store = header_len;
+        image[store..store + 16].copy_from_slice(&AUTHENTICATED_VARIABLE_GUID);
+        image[store + 16..store + 20].copy_from_slice(&(STORE_SIZE as u32).to_le_bytes());
+        image[store + 20] = 0x5A;
+        image[store + 21] = 0xFE;
+        image[store + 22..store + 28].fill(0);
+        image
+    }
+
+    fn load_option(description: &str) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&1u32.to_le_bytes());
+        data.extend_from_slice(&4u16.to_le_bytes());
+        data.extend_from_slice(&encode_name(description));
+        data.extend_from_slice(&[0x7F, 0xFF, 0x04, 0x00]);
+        data
+    }
+
+    fn populated() -> VarStore {
+        let mut store = VarStore::parse(template()).unwrap();
+        assert!(store.variables.is_empty());
+        for (id, description) in [
+            (0, "UiApp"),
+            (1, "UEFI QEMU HARDDISK"),
+            (2, "EFI Internal Shell"),
+        ] {
+            store.set(
+                &EFI_GLOBAL_VARIABLE,
+                &boot_variable_name(id),
+                BOOT_VARIABLE_ATTRIBUTES,
+                load_option(description),
+            );
+        }
+        store.set_boot_order(&[0, 1, 2]);
+        VarStore::parse(store.to_bytes().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip_boot_entries() {
+        let store = populated();
+        assert_eq!(store.boot_order(), vec![0, 1, 2]);
+        let entries = store.boot_entries();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[1].1.description, "UEFI QEMU HARDDISK");
+        assert!(entries[1].1.active());
+    }
+
+    #[test]
+    fn test_reorder_and_delete() {
+        let mut store = populated();
+        store.set_boot_order(&[1, 0]);
+        assert!(store.remove(&EFI_GLOBAL_VARIABLE, "Boot0002"));
+        assert!(!store.remove(&EFI_GLOBAL_VARIABLE, "Boot0002"));
+        let image = store.to_bytes().unwrap();
+
+        let store = VarStore::parse(image.clone()).unwrap();
+        assert_eq!(store.boot_order(), vec![1, 0]);
+        assert_eq!(
+            store
+                .boot_entries()
+                .iter()
+                .map(|(id, _)| *id)
+                .collect::<Vec<_>>(),
+            vec![0, 1]
+        );
+        // The firmware volume header and the area after the store are untouched.
+        assert_eq!(image[..0x48], template()[..0x48]);
+        assert!(image[0x48 + STORE_SIZE..].iter().all(|&b| b == 0xFF));
+    }
+
+    #[test]
+    fn test_superseded_copies_are_ignored() {
+        let mut image = populated().to_bytes().unwrap();
+        // Mark the first variable (Boot0000) as deleted and the second as in
+        // transition; only the deleted one should disappear.
+        let first = align4(0x48 + STORE_HEADER_SIZE);
+        image[first + 2] = VAR_ADDED & 0xFD;
+        let name_size = read_u32(&image, first + 36).unwrap() as usize;
+        let data_size = read_u32(&image, first + 40).unwrap() as usize;
+        let second = align4(first + AUTH_HEADER_SIZE + name_size + data_size);
+        image[second + 2] = VAR_ADDED & VAR_IN_DELETED_TRANSITION;
+
+        let store = VarStore::parse(image).unwrap();
+        let ids: Vec<u16> = store.boot_entries().iter().map(|(id, _)| *id).collect();
+        assert_eq!(ids, vec![1, 2]);
+    }
+
+    #[test]
+    fn test_store_full() {
+        let mut store = VarStore::parse(template()).unwrap();
+        store.set(&EFI_GLOBAL_VARIABLE, "Big", 7, vec![0; STORE_SIZE]);
+        assert!(store.to_bytes().is_err());
+    }
+
+    /// Loads a gzipped image from `tests/fixtures`. The images follow the
+    /// edk2 `VarStore.fdf.inc` layouts: OVMF's 4M build after a first boot,
+    /// and ArmVirtQemu's pristine template padded to 64 MiB.
+    fn fixture(name: &str) -> Vec<u8> {
+        use std::io::Read;
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(name);
+        let mut image = Vec::new();
+        flate2::read::GzDecoder::new(std::fs::File::open(path).unwrap())
+            .read_to_end(&mut image)
+            .unwrap();
+        image
+    }
+
+    #[test]
+    fn test_ovmf_vars_fixture() {
+        let image = fixture("OVMF_VARS_4M.fd.gz");
+        let mut store = VarStore::parse(image.clone()).unwrap();
+        assert!(store.authenticated);
+        assert_eq!(store.store_size, 0x3FFB8);
+        // The first BootOrder copy was superseded by the second.
+        assert_eq!(store.boot_order(), vec![1, 0]);
+        let entries: Vec<(u16, String)> = store
+            .boot_entries()
+            .into_iter()
+            .map(|(id, option)| (id, option.description))
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                (0, "UiApp".to_string()),
+                (1, "UEFI QEMU HARDDISK QM00001 ".to_string())
+            ]
+        );
+        assert_eq!(store.variables.len(), 5);
+
+        store.set_boot_order(&[0, 1]);
+        let edited = store.to_bytes().unwrap();
+        assert_eq!(edited.len(), image.len());
+        // The volume header and the fault-tolerant write blocks are untouched.
+        assert_eq!(edited[..0x48], image[..0x48]);
+        assert_eq!(edited[0x40000..], image[0x40000..]);
+        let reparsed = VarStore::parse(edited).unwrap();
+        assert_eq!(reparsed.boot_order(), vec![0, 1]);
+        assert_eq!(
+            reparsed.get(&EFI_GLOBAL_VARIABLE, "Timeout").unwrap().data,
+            [0, 0]
+        );
+    }
+
+    #[test]
+    fn test_aavmf_vars_fixture() {
+        let image = fixture("AAVMF_VARS.fd.gz");
+        let store = VarStore::parse(image.clone()).unwrap();
+        assert!(store.authenticated);
+        assert!(store.variables.is_empty());
+        assert!(store.to_bytes().unwrap() == image);
+    }
+
+    #[test]
+    fn test_rejects_non_volume() {
+        assert!(VarStore::parse(vec![0; 0x2000]).is_err());
+    }
+
+    #[test]
+    fn test_parse_boot_id() {
+        assert_eq!(parse_boot_id("0003"), Ok(3));
+        assert_eq!(parse_boot_id("Boot000A"), Ok(10));
+        assert!(parse_boot_id("zz").is_err());
+    }
+}
@@ -1,3 +1,7 @@
 pub mod error;
+pub mod process;
+pub mod progress;
 pub mod resource;
 pub mod shell;
+pub mod sparse;
+pub mod time;
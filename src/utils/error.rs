@@ -7,6 +7,14 @@ pub enum Error {
     Var(std::env::VarError),
 }
 impl Error {
+    /// The underlying message without the colored headline, for embedding in
+    /// other output.
+    pub fn message(&self) -> String {
+        match self {
+            Error::Io(err) => err.to_string(),
+            Error::Var(err) => err.to_string(),
+        }
+    }
     fn display_for(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Error::Io(err) => write!(f, "  {}: {}", "IO error".cyan().bold(), err),
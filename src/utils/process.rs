@@ -0,0 +1,28 @@
+use std::fs;
+use std::path::Path;
+
+/// Returns the PIDs of processes holding `path` open, found by walking
+/// `/proc/<pid>/fd`. Processes we are not allowed to inspect are skipped.
+pub fn processes_using(path: &Path) -> Vec<u32> {
+    let Ok(target) = fs::canonicalize(path) else {
+        return Vec::new();
+    };
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+
+    let mut pids: Vec<u32> = entries
+        .flatten()
+        .filter_map(|entry| entry.file_name().to_str()?.parse::<u32>().ok())
+        .filter(|pid| {
+            fs::read_dir(format!("/proc/{}/fd", pid))
+                .map(|fds| {
+                    fds.flatten()
+                        .any(|fd| fs::read_link(fd.path()).is_ok_and(|link| link == target))
+                })
+                .unwrap_or(false)
+        })
+        .collect();
+    pids.sort_unstable();
+    pids
+}
@@ -0,0 +1,41 @@
+use std::io::Read;
+
+/// Counts bytes passing through and prints a percentage on stderr.
+pub struct ProgressReader<R> {
+    inner: R,
+    read: u64,
+    total: u64,
+    shown: Option<u64>,
+}
+
+impl<R> ProgressReader<R> {
+    pub fn new(inner: R, total: u64) -> Self {
+        Self {
+            inner,
+            read: 0,
+            total,
+            shown: None,
+        }
+    }
+}
+
+impl<R: Read> Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read += n as u64;
+        let percent = (self.read * 100)
+            .checked_div(self.total)
+            .unwrap_or(100)
+            .min(100);
+        if self.shown != Some(percent) {
+            self.shown = Some(percent);
+            eprint!(
+                "\r  {:>3}% ({} / {} MiB)",
+                percent,
+                self.read >> 20,
+                self.total >> 20
+            );
+        }
+        Ok(n)
+    }
+}
@@ -1,4 +1,5 @@
 use regex::Regex;
+use std::fmt;
 use std::str::FromStr;
 
 #[derive(Debug, PartialEq, Clone)]
@@ -37,18 +38,44 @@ impl FromStr for ResourceValue {
                 Ok(ResourceValue::Absolute(value * multiplier))
             } else {
                 s.parse::<u128>()
-                    .map(|val| ResourceValue::Absolute(val))
+                    .map(ResourceValue::Absolute)
                     .map_err(|_| format!("Invalid resource value: {}", s))
             }
         }
     }
 }
 
-impl ToString for ResourceValue {
-    fn to_string(&self) -> String {
+/// Parses a disk size such as `64G`, `64GB`, `64GiB`, `1T` or a plain byte
+/// count. Units are binary, as in `qemu-img`.
+pub fn parse_disk_size(s: &str) -> Result<u64, String> {
+    let re = Regex::new(r"^(?i)(\d+(?:\.\d+)?)\s*([KMGTPE]?)(I?B)?$").unwrap();
+    let invalid = || format!("Invalid disk size: {} (e.g. 64G, 64GB or 1T)", s);
+    let caps = re.captures(s.trim()).ok_or_else(invalid)?;
+    let exponent = match caps[2].to_ascii_uppercase().as_str() {
+        "" => 0,
+        "K" => 1,
+        "M" => 2,
+        "G" => 3,
+        "T" => 4,
+        "P" => 5,
+        _ => 6,
+    };
+    // "iB" needs a unit in front of it; a bare "B" means bytes.
+    if exponent == 0 && caps.get(3).is_some_and(|m| m.as_str().len() > 1) {
+        return Err(invalid());
+    }
+    let bytes = caps[1].parse::<f64>().map_err(|_| invalid())? * 1024f64.powi(exponent);
+    if bytes < 1.0 || bytes >= u64::MAX as f64 {
+        return Err(invalid());
+    }
+    Ok(bytes.ceil() as u64)
+}
+
+impl fmt::Display for ResourceValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            ResourceValue::Absolute(val) => val.to_string(),
-            ResourceValue::Percentage(val) => format!("{}%", val),
+            ResourceValue::Absolute(val) => write!(f, "{}", val),
+            ResourceValue::Percentage(val) => write!(f, "{}%", val),
         }
     }
 }
@@ -96,6 +123,21 @@ mod tests {
         assert!(ResourceValue::from_str("10GB").is_err());
     }
 
+    #[test]
+    fn test_parse_disk_size() {
+        assert_eq!(parse_disk_size("64G"), Ok(64 << 30));
+        assert_eq!(parse_disk_size("64GB"), Ok(64 << 30));
+        assert_eq!(parse_disk_size("64gib"), Ok(64 << 30));
+        assert_eq!(parse_disk_size("1T"), Ok(1 << 40));
+        assert_eq!(parse_disk_size("1.5G"), Ok(3 << 29));
+        assert_eq!(parse_disk_size("1073741824"), Ok(1 << 30));
+        assert_eq!(parse_disk_size("512B"), Ok(512));
+        assert!(parse_disk_size("0").is_err());
+        assert!(parse_disk_size("50%").is_err());
+        assert!(parse_disk_size("64X").is_err());
+        assert!(parse_disk_size("64iB").is_err());
+    }
+
     #[test]
     fn test_to_string_absolute() {
         assert_eq!(
@@ -1,5 +1,12 @@
 // src/main.rs
+use crate::modules::app::cache::CacheCommand;
+use crate::modules::app::config::ConfigCommand;
+use crate::modules::app::image::ImageCommand;
+use crate::modules::app::import_build::ImportBuildCommand;
+use crate::modules::app::info::InfoCommand;
 use crate::modules::app::new::NewCommand;
+use crate::modules::app::nvram::NvramCommand;
+use crate::modules::app::port_forward::PortForwardCommand;
 use crate::modules::app::recover::RecoverCommand;
 use crate::modules::app::remove::RemoveCommand;
 use crate::modules::app::run::RunCommand;
@@ -67,7 +74,16 @@ pub struct Args {
 pub enum SubCommands {
     Run(RunCommand),
     List,
-    New(NewCommand),
+    New(Box<NewCommand>),
     Remove(RemoveCommand),
     Recover(RecoverCommand),
+    Reinstall(RecoverCommand),
+    Nvram(NvramCommand),
+    #[clap(name = "images", visible_alias = "image")]
+    Image(ImageCommand),
+    Cache(CacheCommand),
+    ImportBuild(ImportBuildCommand),
+    PortForward(PortForwardCommand),
+    Info(InfoCommand),
+    Config(ConfigCommand),
 }
@@ -0,0 +1,72 @@
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+const BLOCK_SIZE: usize = 4096;
+
+/// Copies `src` into `dst`, seeking over all-zero blocks instead of writing
+/// them so a file destination stays sparse. Returns the number of bytes
+/// read; the caller sets the final length, since trailing zero blocks are
+/// never written.
+pub fn sparse_copy<R: Read, W: Write + Seek>(src: &mut R, dst: &mut W) -> io::Result<u64> {
+    let mut buf = vec![0u8; BLOCK_SIZE * 256];
+    let mut offset = 0u64;
+    loop {
+        let n = read_full(src, &mut buf)?;
+        if n == 0 {
+            return Ok(offset);
+        }
+        for block in buf[..n].chunks(BLOCK_SIZE) {
+            if block.iter().any(|&b| b != 0) {
+                dst.seek(SeekFrom::Start(offset))?;
+                dst.write_all(block)?;
+            }
+            offset += block.len() as u64;
+        }
+    }
+}
+
+/// Fills `buf` as far as the reader allows, so blocks stay aligned even when
+/// the reader returns short reads.
+fn read_full<R: Read>(src: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match src.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_sparse_copy_skips_zero_blocks() {
+        let mut src = vec![0u8; BLOCK_SIZE * 4 + 10];
+        src[5] = 1;
+        src[BLOCK_SIZE * 2 + 7] = 2;
+        src[BLOCK_SIZE * 4 + 9] = 3;
+        // A destination pre-filled with 0xff shows which blocks were written.
+        let mut dst = Cursor::new(vec![0xffu8; src.len()]);
+        let copied = sparse_copy(&mut Cursor::new(&src), &mut dst).unwrap();
+        let dst = dst.into_inner();
+
+        assert_eq!(copied, src.len() as u64);
+        assert_eq!(dst[..BLOCK_SIZE], src[..BLOCK_SIZE]);
+        assert!(dst[BLOCK_SIZE..BLOCK_SIZE * 2].iter().all(|&b| b == 0xff));
+        assert_eq!(
+            dst[BLOCK_SIZE * 2..BLOCK_SIZE * 3],
+            src[BLOCK_SIZE * 2..BLOCK_SIZE * 3]
+        );
+        assert!(
+            dst[BLOCK_SIZE * 3..BLOCK_SIZE * 4]
+                .iter()
+                .all(|&b| b == 0xff)
+        );
+        assert_eq!(dst[BLOCK_SIZE * 4..], src[BLOCK_SIZE * 4..]);
+    }
+}